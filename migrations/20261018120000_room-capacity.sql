@@ -0,0 +1,8 @@
+-- Add migration script here
+ALTER TABLE message
+ADD COLUMN main_slots int NOT NULL DEFAULT 9,
+ADD COLUMN reserve_slots int NOT NULL DEFAULT 6;
+
+ALTER TABLE config
+ADD COLUMN main_slots int,
+ADD COLUMN reserve_slots int;
@@ -0,0 +1,38 @@
+-- Add migration script here
+-- IF NOT EXISTS lets operators add and fill in the column by hand, see below
+ALTER TABLE message
+ADD COLUMN IF NOT EXISTS guild_id bigint;
+
+-- rooms were only ever hosted in a single server before this migration, so we can
+-- attribute them to it when exactly one guild is configured
+UPDATE message
+SET guild_id = (
+        SELECT guild_id
+        FROM config
+    )
+WHERE guild_id IS NULL
+    AND (
+        SELECT COUNT(*)
+        FROM config
+    ) = 1;
+
+-- never guess, and never throw rooms away. the whole migration is rolled back, so it can
+-- simply be run again once the rooms are attributed
+DO $$
+DECLARE
+    unattributed bigint;
+BEGIN
+    SELECT COUNT(*) INTO unattributed FROM message WHERE guild_id IS NULL;
+
+    IF unattributed > 0 THEN
+        RAISE EXCEPTION '% room(s) cannot be attributed to a guild automatically', unattributed
+        USING HINT = 'Run `ALTER TABLE message ADD COLUMN guild_id bigint`, set guild_id '
+            'for every room by hand, then run the migrations again.';
+    END IF;
+END $$;
+
+ALTER TABLE message
+ALTER COLUMN guild_id SET NOT NULL;
+
+ALTER TABLE message
+ADD CONSTRAINT message_guild_id_num_key UNIQUE (guild_id, num);
@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE message
+ADD COLUMN starts_at timestamptz;
@@ -0,0 +1,32 @@
+-- Add migration script here
+CREATE TYPE room_status AS ENUM ('open', 'closed', 'started', 'finished', 'cancelled');
+
+-- IF NOT EXISTS lets operators add and fill in channel_id by hand, see below
+ALTER TABLE message
+ADD COLUMN status room_status NOT NULL DEFAULT 'open',
+ADD COLUMN IF NOT EXISTS channel_id bigint;
+
+-- rooms have always been posted in their guild's fnf channel
+UPDATE message m
+SET channel_id = c.fnf_channel_id
+FROM config c
+WHERE m.guild_id = c.guild_id
+    AND m.channel_id IS NULL;
+
+-- never throw rooms away. the whole migration is rolled back, so it can simply be run
+-- again once the rooms have a channel
+DO $$
+DECLARE
+    unattributed bigint;
+BEGIN
+    SELECT COUNT(*) INTO unattributed FROM message WHERE channel_id IS NULL;
+
+    IF unattributed > 0 THEN
+        RAISE EXCEPTION '% room(s) belong to a guild without an fnf channel', unattributed
+        USING HINT = 'Run `ALTER TABLE message ADD COLUMN channel_id bigint`, set channel_id '
+            'for those rooms by hand, then run the migrations again.';
+    END IF;
+END $$;
+
+ALTER TABLE message
+ALTER COLUMN channel_id SET NOT NULL;
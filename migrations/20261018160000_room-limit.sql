@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE config
+ADD COLUMN room_limit int;
@@ -0,0 +1,15 @@
+-- Add migration script here
+CREATE TABLE reminder (
+    message_id bigint NOT NULL REFERENCES message(message_id) ON DELETE CASCADE,
+    minutes_before int NOT NULL,
+    remind_at timestamptz NOT NULL,
+    sent boolean NOT NULL DEFAULT false,
+    PRIMARY KEY (message_id, minutes_before)
+);
+
+CREATE INDEX reminder_unsent_idx ON reminder (remind_at)
+WHERE NOT sent;
+
+ALTER TABLE config
+ADD COLUMN reminder_offsets int[],
+ADD COLUMN reminder_ping boolean NOT NULL DEFAULT false;
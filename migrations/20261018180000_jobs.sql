@@ -0,0 +1,37 @@
+-- Add migration script here
+CREATE TYPE job_status AS ENUM ('pending', 'running', 'done', 'failed');
+
+CREATE TABLE job (
+    id bigserial PRIMARY KEY,
+    kind text NOT NULL,
+    message_id bigint REFERENCES message(message_id) ON DELETE CASCADE,
+    payload jsonb NOT NULL,
+    status job_status NOT NULL DEFAULT 'pending',
+    run_at timestamptz NOT NULL,
+    attempts int NOT NULL DEFAULT 0,
+    locked_until timestamptz,
+    last_error text,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    completed_at timestamptz
+);
+
+CREATE INDEX job_due_idx ON job (run_at)
+WHERE status IN ('pending', 'running');
+
+-- pending reminders become jobs, sent ones are no longer interesting
+INSERT INTO job (kind, message_id, payload, run_at)
+SELECT 'reminder',
+    message_id,
+    jsonb_build_object(
+        'kind',
+        'reminder',
+        'message_id',
+        message_id,
+        'minutes_before',
+        minutes_before
+    ),
+    remind_at
+FROM reminder
+WHERE NOT sent;
+
+DROP TABLE reminder;
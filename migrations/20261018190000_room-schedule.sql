@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE config
+ADD COLUMN close_lead_minutes int,
+ADD COLUMN room_duration_minutes int;
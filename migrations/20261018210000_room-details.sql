@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE message
+ADD COLUMN host_id bigint,
+ADD COLUMN title text;
@@ -0,0 +1,8 @@
+-- Add migration script here
+CREATE TYPE signup_mode AS ENUM ('reactions', 'buttons');
+
+ALTER TABLE config ADD COLUMN signup_mode signup_mode;
+
+-- rooms hosted before buttons existed keep taking signups through reactions
+ALTER TABLE message ADD COLUMN signup_mode signup_mode NOT NULL DEFAULT 'reactions';
+ALTER TABLE message ALTER COLUMN signup_mode DROP DEFAULT;
@@ -0,0 +1,8 @@
+-- Add migration script here
+-- players dropped from rooms because they left the guild, shown to hosts
+CREATE TABLE departure (
+    message_id bigint NOT NULL REFERENCES message(message_id) ON DELETE CASCADE,
+    user_id bigint NOT NULL,
+    left_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (message_id, user_id)
+);
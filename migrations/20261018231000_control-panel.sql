@@ -0,0 +1,7 @@
+-- Add migration script here
+-- staff channel for room control panels, they are DMed to the host when unset
+ALTER TABLE config ADD COLUMN panel_channel_id bigint;
+
+ALTER TABLE message
+ADD COLUMN panel_channel_id bigint,
+ADD COLUMN panel_message_id bigint;
@@ -0,0 +1,15 @@
+-- Add migration script here
+CREATE TYPE channel_cleanup AS ENUM ('archive', 'delete');
+
+ALTER TABLE config
+ADD COLUMN room_category_id bigint,
+ADD COLUMN room_voice boolean NOT NULL DEFAULT false,
+ADD COLUMN room_cleanup channel_cleanup;
+
+-- private channels the bot created for rooms
+CREATE TABLE room_channel (
+    channel_id bigint PRIMARY KEY,
+    message_id bigint NOT NULL REFERENCES message(message_id) ON DELETE CASCADE,
+    voice boolean NOT NULL,
+    archived boolean NOT NULL DEFAULT false
+);
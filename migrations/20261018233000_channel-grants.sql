@@ -0,0 +1,9 @@
+-- Add migration script here
+-- member overwrites the bot created for a room's players, so it never revokes ones staff
+-- set by hand. a player can be granted access to one channel through several rooms
+CREATE TABLE channel_grant (
+    channel_id bigint NOT NULL,
+    user_id bigint NOT NULL,
+    message_id bigint NOT NULL REFERENCES message(message_id) ON DELETE CASCADE,
+    PRIMARY KEY (channel_id, user_id, message_id)
+);
@@ -0,0 +1,11 @@
+-- Add migration script here
+ALTER TABLE config
+ADD COLUMN participant_role_id bigint;
+
+-- participant roles the bot gave a room's players, so it never strips ones staff gave by hand
+CREATE TABLE role_grant (
+    message_id bigint NOT NULL REFERENCES message(message_id) ON DELETE CASCADE,
+    user_id bigint NOT NULL,
+    role_id bigint NOT NULL,
+    PRIMARY KEY (message_id, user_id, role_id)
+);
@@ -0,0 +1,31 @@
+-- Add migration script here
+CREATE TYPE signup_event_kind AS ENUM ('join', 'leave', 'promote', 'demote', 'kick', 'moved');
+
+-- every change to a room's roster, in the order it happened. actor_id is whoever caused it,
+-- NULL meaning the bot. a room's history outlives the room, so events remember which room
+-- they belonged to themselves instead of pointing at it
+CREATE TABLE signup_event (
+    event_id bigserial PRIMARY KEY,
+    message_id bigint NOT NULL,
+    guild_id bigint NOT NULL,
+    num int NOT NULL,
+    user_id bigint NOT NULL,
+    kind signup_event_kind NOT NULL,
+    actor_id bigint,
+    detail text,
+    created_at timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE INDEX signup_event_message_id_idx ON signup_event (message_id);
+CREATE INDEX signup_event_guild_id_num_idx ON signup_event (guild_id, num);
+
+-- the log is append-only
+CREATE FUNCTION forbid_signup_event_changes() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'signup_event is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER signup_event_append_only
+BEFORE UPDATE OR DELETE ON signup_event
+FOR EACH ROW EXECUTE FUNCTION forbid_signup_event_changes();
@@ -0,0 +1,7 @@
+-- Add migration script here
+-- side effects of a job that already happened, so a retried job doesn't repeat them
+CREATE TABLE job_step (
+    job_id bigint NOT NULL REFERENCES job(id) ON DELETE CASCADE,
+    step text NOT NULL,
+    PRIMARY KEY (job_id, step)
+);
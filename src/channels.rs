@@ -0,0 +1,656 @@
+use poise::serenity_prelude::{
+    self as serenity,
+    ChannelId,
+    ChannelType,
+    GuildChannel,
+    GuildId,
+    Mentionable,
+    MessageId,
+    PermissionOverwrite,
+    PermissionOverwriteType,
+    Permissions,
+    RoleId,
+    UserId,
+};
+use sqlx::PgPool;
+use tracing::{error, info};
+
+use crate::datetime::format_timestamp;
+use crate::roles::{get_participant_role, sync_participant_role};
+use crate::room::{get_roster, get_roster_split, RosterGroup};
+use crate::utils::{is_discord_error, run_batched, Progress, UNKNOWN_CHANNEL};
+use crate::Error;
+
+pub const DEFAULT_CHANNEL_CLEANUP: ChannelCleanup = ChannelCleanup::Archive;
+
+/// What happens to a room's private channels once it is over.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, sqlx::Type, poise::ChoiceParameter)]
+#[sqlx(type_name = "channel_cleanup", rename_all = "lowercase")]
+pub enum ChannelCleanup {
+    /// Players lose access, but staff can still read the channels.
+    #[name = "archive"]
+    Archive,
+    #[name = "delete"]
+    Delete,
+}
+
+fn text_access() -> Permissions {
+    Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES | Permissions::READ_MESSAGE_HISTORY
+}
+
+fn voice_access() -> Permissions {
+    Permissions::VIEW_CHANNEL | Permissions::CONNECT | Permissions::SPEAK
+}
+
+fn member_overwrite(user_id: UserId, allow: Permissions) -> PermissionOverwrite {
+    PermissionOverwrite {
+        allow,
+        deny: Permissions::empty(),
+        kind: PermissionOverwriteType::Member(user_id),
+    }
+}
+
+/// Creates private channels for a room under the guild's room category, which only its
+/// players, its host and the bot can see. Does nothing if the guild has no room category.
+pub async fn create_room_channels(
+    ctx: &serenity::Context,
+    pool: &PgPool,
+    message_id: MessageId,
+) -> Result<(), Error> {
+    let room = sqlx::query!(
+        r#"SELECT m.guild_id, m.num, m.host_id, m.starts_at, c.room_category_id,
+        c.room_voice as "room_voice?",
+        exists (SELECT 1 FROM room_channel r WHERE r.message_id = m.message_id AND NOT r.voice)
+        as "has_text!",
+        exists (SELECT 1 FROM room_channel r WHERE r.message_id = m.message_id AND r.voice)
+        as "has_voice!"
+        FROM message m LEFT JOIN config c ON c.guild_id = m.guild_id
+        WHERE m.message_id = $1"#,
+        message_id.0 as i64
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let mut members = get_roster(pool, message_id).await?;
+
+    // a room that was reopened and closed again keeps its channels, but may have new players
+    for &user_id in &members {
+        grant_channel_access(ctx, pool, message_id, UserId(user_id as u64)).await?;
+    }
+
+    // either channel may be missing if creating it failed last time
+    let mut kinds = Vec::new();
+    if !room.has_text {
+        kinds.push((ChannelType::Text, text_access()));
+    }
+    if room.room_voice.unwrap_or_default() && !room.has_voice {
+        kinds.push((ChannelType::Voice, voice_access()));
+    }
+
+    let category = match room.room_category_id {
+        Some(id) if !kinds.is_empty() => ChannelId(id as u64),
+        _ => return Ok(()),
+    };
+
+    let guild_id = GuildId(room.guild_id as u64);
+
+    members.extend(room.host_id);
+    let bot_id = ctx.cache.current_user_id();
+    members.push(bot_id.0 as i64);
+    members.sort_unstable();
+    members.dedup();
+
+    let overwrites = |allow: Permissions| {
+        let mut overwrites = vec![PermissionOverwrite {
+            allow: Permissions::empty(),
+            deny: Permissions::VIEW_CHANNEL,
+            kind: PermissionOverwriteType::Role(RoleId(guild_id.0)),
+        }];
+        overwrites.extend(
+            members
+                .iter()
+                .map(|&u| member_overwrite(UserId(u as u64), allow)),
+        );
+        overwrites
+    };
+
+    for (kind, allow) in kinds {
+        let channel = guild_id
+            .create_channel(ctx, |c| {
+                c.name(format!("room-{}", room.num))
+                    .kind(kind)
+                    .category(category)
+                    .permissions(overwrites(allow))
+            })
+            .await?;
+
+        sqlx::query!(
+            "INSERT INTO room_channel (channel_id, message_id, voice) VALUES ($1, $2, $3)",
+            channel.id.0 as i64,
+            message_id.0 as i64,
+            kind == ChannelType::Voice
+        )
+        .execute(pool)
+        .await?;
+
+        for &user_id in members.iter().filter(|&&u| u != bot_id.0 as i64) {
+            record_grant(pool, channel.id, user_id, message_id).await?;
+        }
+
+        info!("created channel {} for room {}", channel.id, message_id.0);
+
+        if kind == ChannelType::Text {
+            let mut content = format!("This is the private channel for Room #{}.", room.num);
+            if let Some(starts_at) = room.starts_at {
+                content.push_str(&format!(
+                    " The room starts {}.",
+                    format_timestamp(starts_at)
+                ));
+            }
+            channel.say(ctx, content).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Lets a player who joined a room after its channels were created into them. Channels
+/// the room already gave them access to are skipped.
+pub async fn grant_channel_access(
+    ctx: &serenity::Context,
+    pool: &PgPool,
+    message_id: MessageId,
+    user_id: UserId,
+) -> Result<(), Error> {
+    let channels = sqlx::query!(
+        "SELECT r.channel_id, r.voice FROM room_channel r
+        WHERE r.message_id = $1 AND NOT r.archived AND NOT exists (
+            SELECT 1 FROM channel_grant g
+            WHERE g.channel_id = r.channel_id AND g.message_id = r.message_id AND g.user_id = $2
+        )",
+        message_id.0 as i64,
+        user_id.0 as i64,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    for record in channels {
+        let channel = match ChannelId(record.channel_id as u64)
+            .to_channel(ctx)
+            .await?
+            .guild()
+        {
+            Some(c) => c,
+            None => continue,
+        };
+
+        let allow = if record.voice {
+            voice_access()
+        } else {
+            text_access()
+        };
+
+        grant_access(ctx, pool, message_id, &channel, user_id, allow).await?;
+    }
+
+    Ok(())
+}
+
+/// Gives a room's player access to `channel` and remembers that the bot did so. Returns
+/// `false` without touching anything if the player already has an overwrite the bot
+/// didn't create, as that one was set by staff.
+pub async fn grant_access(
+    ctx: &serenity::Context,
+    pool: &PgPool,
+    message_id: MessageId,
+    channel: &GuildChannel,
+    user_id: UserId,
+    allow: Permissions,
+) -> Result<bool, Error> {
+    if !would_grant(pool, channel, user_id).await? {
+        return Ok(false);
+    }
+
+    channel
+        .id
+        .create_permission(ctx, &member_overwrite(user_id, allow))
+        .await?;
+    record_grant(pool, channel.id, user_id.0 as i64, message_id).await?;
+
+    Ok(true)
+}
+
+async fn record_grant(
+    pool: &PgPool,
+    channel_id: ChannelId,
+    user_id: i64,
+    message_id: MessageId,
+) -> Result<(), Error> {
+    sqlx::query!(
+        "INSERT INTO channel_grant (channel_id, user_id, message_id) VALUES ($1, $2, $3)
+        ON CONFLICT DO NOTHING",
+        channel_id.0 as i64,
+        user_id,
+        message_id.0 as i64,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Takes back the access a room gave a player to `channel_id`. The overwrite itself is only
+/// deleted once no other room needs it.
+async fn revoke_grant(
+    ctx: &serenity::Context,
+    pool: &PgPool,
+    channel_id: ChannelId,
+    user_id: UserId,
+    message_id: MessageId,
+) -> Result<(), Error> {
+    let still_needed = sqlx::query!(
+        r#"SELECT exists (
+            SELECT 1 FROM channel_grant
+            WHERE channel_id = $1 AND user_id = $2 AND message_id <> $3
+        ) as "still_needed!""#,
+        channel_id.0 as i64,
+        user_id.0 as i64,
+        message_id.0 as i64,
+    )
+    .fetch_one(pool)
+    .await?
+    .still_needed;
+
+    if !still_needed {
+        let deleted = channel_id
+            .delete_permission(ctx, PermissionOverwriteType::Member(user_id))
+            .await;
+
+        // the overwrite went away along with a deleted channel
+        match deleted {
+            Ok(()) => (),
+            Err(e) if is_discord_error(&e, &[UNKNOWN_CHANNEL]) => (),
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    sqlx::query!(
+        "DELETE FROM channel_grant WHERE channel_id = $1 AND user_id = $2 AND message_id = $3",
+        channel_id.0 as i64,
+        user_id.0 as i64,
+        message_id.0 as i64,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Takes back every channel access a room gave a player, for when they leave or are kicked.
+pub async fn revoke_channel_access(
+    ctx: &serenity::Context,
+    pool: &PgPool,
+    message_id: MessageId,
+    user_id: UserId,
+) -> Result<(), Error> {
+    let grants = sqlx::query!(
+        "SELECT channel_id FROM channel_grant WHERE message_id = $1 AND user_id = $2",
+        message_id.0 as i64,
+        user_id.0 as i64,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    for grant in grants {
+        let channel_id = ChannelId(grant.channel_id as u64);
+        revoke_grant(ctx, pool, channel_id, user_id, message_id).await?;
+    }
+
+    Ok(())
+}
+
+/// Takes back every channel access a room gave anyone, for when the room is removed.
+pub async fn revoke_room_access(
+    ctx: &serenity::Context,
+    pool: &PgPool,
+    message_id: MessageId,
+) -> Result<(), Error> {
+    let grants = sqlx::query!(
+        "SELECT channel_id, user_id FROM channel_grant WHERE message_id = $1",
+        message_id.0 as i64,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    for grant in grants {
+        let channel_id = ChannelId(grant.channel_id as u64);
+        let user_id = UserId(grant.user_id as u64);
+        revoke_grant(ctx, pool, channel_id, user_id, message_id).await?;
+    }
+
+    Ok(())
+}
+
+/// Lets a room's players see and talk in `channel_id`, reporting each player's outcome in
+/// a progress embed in `report_channel` and returning a summary. Guilds with a participant
+/// role get the role handed to the main roster and allowed in the channel instead of one
+/// overwrite per player. A dry run only reports what would happen.
+pub async fn add_players(
+    ctx: &serenity::Context,
+    pool: &PgPool,
+    message_id: MessageId,
+    channel_id: ChannelId,
+    group: RosterGroup,
+    dry_run: bool,
+    report_channel: Option<ChannelId>,
+) -> Result<String, Error> {
+    let channel = match channel_id.to_channel(ctx).await?.guild() {
+        Some(c) => c,
+        None => return Err("Players can only be added to server channels.".into()),
+    };
+
+    let (main, reserves) = get_roster_split(pool, message_id).await?;
+    let role_id = get_participant_role(pool, message_id).await?;
+    let via_role = role_id.filter(|_| group.includes_main());
+
+    let mut targets: Vec<i64> = Vec::new();
+    if group.includes_main() && via_role.is_none() {
+        targets.extend(&main);
+    }
+    if group.includes_reserves() {
+        targets.extend(&reserves);
+    }
+    let targets = targets
+        .into_iter()
+        .map(|u| UserId(u as u64))
+        .collect::<Vec<_>>();
+
+    let mut summary = Vec::new();
+
+    if let Some(role_id) = via_role {
+        let kind = PermissionOverwriteType::Role(role_id);
+        let has_overwrite = channel.permission_overwrites.iter().any(|o| o.kind == kind);
+
+        if dry_run {
+            summary.push(format!(
+                "{} main roster player(s) would get {}{}.",
+                main.len(),
+                role_id.mention(),
+                if has_overwrite {
+                    ""
+                } else {
+                    ", which would be allowed in the channel"
+                }
+            ));
+        } else {
+            if !has_overwrite {
+                channel_id
+                    .create_permission(ctx, &PermissionOverwrite {
+                        allow: text_access(),
+                        deny: Permissions::empty(),
+                        kind,
+                    })
+                    .await?;
+            }
+
+            sync_participant_role(ctx, pool, message_id, report_channel).await?;
+            summary.push(format!("Gave the main roster {}.", role_id.mention()));
+        }
+    }
+
+    if targets.is_empty() {
+        if summary.is_empty() {
+            summary.push("There are no players to add.".to_string());
+        }
+        return Ok(summary.join("\n"));
+    }
+
+    let title = format!("Adding players to #{}", channel.name);
+
+    if dry_run {
+        let mut added = 0;
+        let mut lines = Vec::new();
+        for &user_id in &targets {
+            if would_grant(pool, &channel, user_id).await? {
+                added += 1;
+                lines.push(format!("Would add {}", user_id.mention()));
+            } else {
+                lines.push(format!(
+                    "Would skip {}, who has a custom overwrite",
+                    user_id.mention()
+                ));
+            }
+        }
+
+        let progress = Progress::start(ctx, report_channel, format!("Dry run: {title}"), 0).await;
+        progress.finish(&lines.join("\n")).await;
+
+        summary.push(format!(
+            "Dry run: {added} player(s) would be added to {}.",
+            channel_id.mention()
+        ));
+        return Ok(summary.join("\n"));
+    }
+
+    let mut progress = Progress::start(ctx, report_channel, title, targets.len()).await;
+    let results = run_batched(targets, &mut progress, |user_id| {
+        let (ctx, pool, channel) = (ctx.clone(), pool.clone(), channel.clone());
+        async move { grant_access(&ctx, &pool, message_id, &channel, user_id, text_access()).await }
+    })
+    .await;
+
+    let (mut added, mut skipped, mut failed) = (0, 0, 0);
+    let mut lines = Vec::new();
+    for (user_id, result) in results {
+        let line = match result {
+            Ok(true) => {
+                added += 1;
+                format!("Added {}", user_id.mention())
+            },
+            Ok(false) => {
+                skipped += 1;
+                format!("Skipped {}, who has a custom overwrite", user_id.mention())
+            },
+            Err(e) => {
+                failed += 1;
+                format!("Failed to add {}: {e}", user_id.mention())
+            },
+        };
+        lines.push(line);
+    }
+    progress.finish(&lines.join("\n")).await;
+
+    summary.push(format!(
+        "Added {added} player(s) to {}, skipped {skipped} and failed to add {failed}.",
+        channel_id.mention()
+    ));
+    Ok(summary.join("\n"))
+}
+
+/// Whether the bot may create an overwrite for the player, meaning they have none yet or
+/// only ones the bot created.
+async fn would_grant(
+    pool: &PgPool,
+    channel: &GuildChannel,
+    user_id: UserId,
+) -> Result<bool, Error> {
+    let has_overwrite = channel
+        .permission_overwrites
+        .iter()
+        .any(|o| o.kind == PermissionOverwriteType::Member(user_id));
+
+    if !has_overwrite {
+        return Ok(true);
+    }
+
+    let granted = sqlx::query!(
+        r#"SELECT exists (SELECT 1 FROM channel_grant WHERE channel_id = $1 AND user_id = $2)
+        as "granted!""#,
+        channel.id.0 as i64,
+        user_id.0 as i64,
+    )
+    .fetch_one(pool)
+    .await?
+    .granted;
+
+    Ok(granted)
+}
+
+/// Takes back the access [`add_players`] gave a room's players to `channel_id`, reporting
+/// each player's outcome in a progress embed and returning a summary. Overwrites set by
+/// staff are left alone.
+pub async fn remove_players(
+    ctx: &serenity::Context,
+    pool: &PgPool,
+    message_id: MessageId,
+    channel_id: ChannelId,
+    report_channel: Option<ChannelId>,
+) -> Result<String, Error> {
+    let grants = sqlx::query!(
+        "SELECT user_id FROM channel_grant WHERE message_id = $1 AND channel_id = $2",
+        message_id.0 as i64,
+        channel_id.0 as i64,
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|r| UserId(r.user_id as u64))
+    .collect::<Vec<_>>();
+
+    if grants.is_empty() {
+        return Ok("None of the room's players were added to that channel.".to_string());
+    }
+
+    let mut progress = Progress::start(
+        ctx,
+        report_channel,
+        "Removing players from the channel",
+        grants.len(),
+    )
+    .await;
+    let results = run_batched(grants, &mut progress, |user_id| {
+        let (ctx, pool) = (ctx.clone(), pool.clone());
+        async move { revoke_grant(&ctx, &pool, channel_id, user_id, message_id).await }
+    })
+    .await;
+
+    let mut removed = 0;
+    let mut lines = Vec::new();
+    for (user_id, result) in results {
+        match result {
+            Ok(()) => {
+                removed += 1;
+                lines.push(format!("Removed {}", user_id.mention()));
+            },
+            Err(e) => lines.push(format!("Failed to remove {}: {e}", user_id.mention())),
+        }
+    }
+    progress.finish(&lines.join("\n")).await;
+
+    Ok(format!(
+        "Removed {removed} of {} player(s) from {}.",
+        lines.len(),
+        channel_id.mention()
+    ))
+}
+
+/// Archives or deletes a room's channels, depending on the guild's configuration.
+/// Archiving takes back the access the bot gave, leaving the channels to staff.
+pub async fn cleanup_room_channels(
+    ctx: &serenity::Context,
+    pool: &PgPool,
+    message_id: MessageId,
+) -> Result<(), Error> {
+    let channels = sqlx::query!(
+        r#"SELECT r.channel_id, c.room_cleanup as "room_cleanup?: ChannelCleanup"
+        FROM room_channel r
+        JOIN message m ON m.message_id = r.message_id
+        LEFT JOIN config c ON c.guild_id = m.guild_id
+        WHERE r.message_id = $1 AND NOT r.archived"#,
+        message_id.0 as i64
+    )
+    .fetch_all(pool)
+    .await?;
+
+    for channel in channels {
+        let channel_id = ChannelId(channel.channel_id as u64);
+
+        // channels deleted while the bot was offline need no cleanup, only forgetting
+        let guild_channel = match channel_id.to_channel(ctx).await {
+            Ok(c) => c.guild(),
+            Err(e) if is_discord_error(&e, &[UNKNOWN_CHANNEL]) => {
+                forget_channel(pool, channel_id).await?;
+                info!(
+                    "forgot deleted channel {} of room {}",
+                    channel_id, message_id.0
+                );
+                continue;
+            },
+            Err(e) => return Err(e.into()),
+        };
+
+        match channel.room_cleanup.unwrap_or(DEFAULT_CHANNEL_CLEANUP) {
+            ChannelCleanup::Delete => {
+                match channel_id.delete(ctx).await {
+                    Ok(_) => (),
+                    Err(e) if is_discord_error(&e, &[UNKNOWN_CHANNEL]) => (),
+                    Err(e) => return Err(e.into()),
+                }
+                forget_channel(pool, channel_id).await?;
+            },
+            ChannelCleanup::Archive => {
+                let grants = sqlx::query!(
+                    "SELECT user_id FROM channel_grant WHERE message_id = $1 AND channel_id = $2",
+                    message_id.0 as i64,
+                    channel.channel_id,
+                )
+                .fetch_all(pool)
+                .await?;
+
+                for grant in grants {
+                    let user_id = UserId(grant.user_id as u64);
+                    // keep going, a leftover overwrite is better than a half archived channel
+                    if let Err(e) = revoke_grant(ctx, pool, channel_id, user_id, message_id).await {
+                        error!("error revoking access to channel {channel_id}: {e}");
+                    }
+                }
+
+                if let Some(guild_channel) = guild_channel {
+                    channel_id
+                        .edit(ctx, |c| c.name(format!("archived-{}", guild_channel.name)))
+                        .await?;
+                }
+
+                sqlx::query!(
+                    "UPDATE room_channel SET archived = true WHERE channel_id = $1",
+                    channel.channel_id
+                )
+                .execute(pool)
+                .await?;
+            },
+        }
+
+        info!("cleaned up channel {} of room {}", channel_id, message_id.0);
+    }
+
+    Ok(())
+}
+
+/// Forgets everything the bot tracked about a channel that no longer exists.
+pub async fn forget_channel(pool: &PgPool, channel_id: ChannelId) -> Result<(), Error> {
+    sqlx::query!(
+        "DELETE FROM room_channel WHERE channel_id = $1",
+        channel_id.0 as i64
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM channel_grant WHERE channel_id = $1",
+        channel_id.0 as i64
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
@@ -1,35 +1,92 @@
-use poise::serenity_prelude::{
-    self as serenity,
-    CacheHttp,
-    Mentionable,
-    PermissionOverwrite,
-    PermissionOverwriteType,
-    Permissions,
-};
+use poise::serenity_prelude::{self as serenity, ArgumentConvert, CacheHttp, Mentionable};
 use tracing::error;
 
-use crate::utils::{confirm_prompt, get_message_id, get_message_link};
-use crate::{invite_url, Context, Error, GuildConfig, EMBED_COLOUR, REACT_STR};
+use crate::channels::{
+    add_players,
+    grant_channel_access,
+    remove_players,
+    revoke_channel_access,
+    ChannelCleanup,
+};
+use crate::datetime::{format_timestamp, parse_datetime};
+use crate::history::{find_history_room, get_timeline, SignupEventKind};
+use crate::jobs::schedule_room_jobs;
+use crate::panel::{send_panel, update_panel};
+use crate::roles::refresh_participant_role;
+use crate::room::{
+    cancel_room,
+    change_status,
+    delete_room,
+    get_roster,
+    move_player,
+    notify_roster_changes,
+    register_player,
+    room_buttons,
+    room_description,
+    roster_embed,
+    swap_players,
+    update_room_embed,
+    withdraw_player,
+    Registration,
+    RoomStatus,
+    RosterGroup,
+    SignupMode,
+};
+use crate::utils::{
+    can_manage_rooms,
+    confirm_prompt,
+    dm_user,
+    get_default_capacity,
+    get_message_id,
+    get_message_link,
+    get_room_capacity,
+    get_room_limit,
+    get_signup_mode,
+    split_message,
+    Capacity,
+};
+use crate::{invite_url, Context, Error, EMBED_COLOUR};
+
+#[derive(Debug, poise::ChoiceParameter)]
+pub enum RoomField {
+    #[name = "time"]
+    Time,
+    #[name = "host"]
+    Host,
+    #[name = "title"]
+    Title,
+    #[name = "capacity"]
+    Capacity,
+}
 
 /// Set up self-role reaction message for a new room.
-#[poise::command(prefix_command, guild_only, check = "is_host_or_mod")]
+///
+/// As a prefix command, quote a datetime of several words, e.g. `~host "tomorrow 8pm" @host 9+6`.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    check = "is_host_or_mod",
+    default_member_permissions = "MANAGE_MESSAGES"
+)]
 pub async fn host(
     ctx: Context<'_>,
+    #[description = "Datetime for the room, e.g. `tomorrow 8pm` or `in 2h`"] date_time: String,
     #[description = "Optional host for the room"] host: Option<serenity::Member>,
-    #[description = "Datetime for the room"] date_time: String,
+    #[description = "Optional capacity as `main+reserve`, e.g. `9+6`"] capacity: Option<Capacity>,
 ) -> Result<(), Error> {
     let guild_id = match ctx.guild_id() {
         Some(gid) => gid,
         None => return Ok(()),
     };
 
-    // get room number
-    let room_num = sqlx::query!("SELECT MAX(num) FROM message")
-        .fetch_one(&ctx.data().db_pool)
-        .await?
-        .max
-        .unwrap_or_default()
-        + 1;
+    // slash commands must be answered within 3 seconds, posting the room can take longer
+    ctx.defer().await?;
+
+    let starts_at = parse_datetime(&date_time, chrono::Utc::now())?;
+    let host_id = host.map_or(ctx.author().id, |h| h.user.id);
+
+    let capacity = capacity.unwrap_or_else(|| get_default_capacity(ctx.data(), guild_id));
 
     let channel = if let Some(id) = ctx
         .data()
@@ -43,33 +100,92 @@ pub async fn host(
         return Err("fnf channel not set".into());
     };
 
+    let mode = get_signup_mode(ctx.data(), guild_id);
+
+    // rooms are numbered per guild, so the guild stays locked until the room is saved.
+    // snowflakes are unique, so this lock can't collide with the ones taken for players
+    let mut tx = ctx.data().db_pool.begin().await?;
+    sqlx::query("SELECT pg_advisory_xact_lock($1)")
+        .bind(guild_id.0 as i64)
+        .execute(&mut tx)
+        .await?;
+
+    // get room number
+    let room_num = sqlx::query!(
+        "SELECT MAX(num) FROM message WHERE guild_id = $1",
+        guild_id.0 as i64
+    )
+    .fetch_one(&mut tx)
+    .await?
+    .max
+    .unwrap_or_default()
+        + 1;
+
     let msg = channel
         .send_message(&ctx, |m| {
+            if mode == SignupMode::Buttons {
+                m.set_components(room_buttons(RoomStatus::Open));
+            }
             m.embed(|e| {
-                e.colour(EMBED_COLOUR)
-                    .title(format!("Room #{}", room_num))
-                    .description(format!(
-                        "{} is hosting a room at **{date_time}!**",
-                        host.map_or_else(|| ctx.author().mention(), |h| h.mention())
-                    ))
-                    .footer(|f| f.text(format!("15/15 spots available | {REACT_STR}")))
+                e.colour(RoomStatus::Open.colour())
+                    .title(RoomStatus::Open.title(room_num, None))
+                    .description(room_description(Some(host_id), starts_at))
+                    .footer(|f| f.text(RoomStatus::Open.footer(capacity, 0, mode)))
             })
         })
         .await?;
 
-    msg.react(&ctx, '✅').await?;
-    msg.react(&ctx, '❌').await?;
+    let saved: Result<(), Error> = async {
+        if mode == SignupMode::Reactions {
+            msg.react(&ctx, '✅').await?;
+            msg.react(&ctx, '❌').await?;
+        }
 
-    sqlx::query!(
-        "INSERT INTO message (message_id, num) VALUES($1, $2) ON CONFLICT (message_id) DO NOTHING",
-        msg.id.0 as i64,
-        room_num,
-    )
-    .execute(&ctx.data().db_pool)
-    .await?;
+        sqlx::query!(
+            "INSERT INTO message
+            (message_id, guild_id, channel_id, num, main_slots, reserve_slots, starts_at,
+            host_id, signup_mode)
+            VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            msg.id.0 as i64,
+            guild_id.0 as i64,
+            channel.0 as i64,
+            room_num,
+            capacity.main,
+            capacity.reserve,
+            starts_at,
+            host_id.0 as i64,
+            mode as SignupMode,
+        )
+        .execute(&mut tx)
+        .await?;
+
+        Ok(tx.commit().await?)
+    }
+    .await;
+
+    // a signup message the bot doesn't track would only confuse players
+    if let Err(e) = saved {
+        if let Err(e) = msg.delete(&ctx).await {
+            error!("unable to delete unsaved room message {}: {e}", msg.id);
+        }
+        return Err(e);
+    }
 
     ctx.data().messages.insert(msg.id.0);
 
+    schedule_room_jobs(&ctx.data().db_pool, guild_id, msg.id, starts_at).await?;
+
+    // the room works fine without a panel, so the host only needs to know it's missing
+    if let Err(e) = send_panel(ctx.serenity_context(), ctx.data(), msg.id).await {
+        error!("error sending panel of room {}: {e}", msg.id);
+        ctx.say(format!(
+            "Self-role reaction message was set up successfully, \
+            but the control panel couldn't be sent: {e}"
+        ))
+        .await?;
+        return Ok(());
+    }
+
     ctx.say("Self-role reaction message was set up successfully.")
         .await?;
 
@@ -79,99 +195,276 @@ pub async fn host(
 /// Shows the users that signed up for the room.
 #[poise::command(
     prefix_command,
+    slash_command,
     aliases("reacts"),
     guild_only,
-    check = "is_host_or_mod"
+    check = "is_host_or_mod",
+    default_member_permissions = "MANAGE_MESSAGES"
 )]
 pub async fn registrations(
     ctx: Context<'_>,
     #[description = "Room number or message ID for the room"]
+    #[autocomplete = "autocomplete_room"]
     #[rename = "room"]
     #[rest]
     room_input: String,
 ) -> Result<(), Error> {
-    let message_id = get_message_id(&room_input, &ctx.data().db_pool).await?;
-
-    let room_num = if let Some(res) = sqlx::query!(
-        "SELECT num FROM message WHERE message_id = $1",
-        message_id.0 as i64
-    )
-    .fetch_optional(&ctx.data().db_pool)
-    .await?
-    {
-        res.num
-    } else {
-        return Err("unable to find room with given number or message ID".into());
+    let guild_id = match ctx.guild_id() {
+        Some(gid) => gid,
+        None => return Ok(()),
     };
 
-    let records = sqlx::query!(
-        "SELECT user_id FROM signup WHERE message_id = $1 ORDER BY react_num",
-        message_id.0 as i64
-    )
-    .fetch_all(&ctx.data().db_pool)
+    let message_id = get_message_id(&room_input, guild_id, &ctx.data().db_pool).await?;
+
+    let link = get_message_link(message_id.0, ctx.data(), guild_id);
+    let embed = roster_embed(&ctx.data().db_pool, message_id, link, true).await?;
+
+    ctx.send(|m| {
+        m.embed(|e| {
+            e.0 = embed.0;
+            e
+        })
+    })
     .await?;
 
-    let mut embed = serenity::CreateEmbed::default();
-    embed.colour(EMBED_COLOUR);
+    Ok(())
+}
 
+/// Shows every join, leave, promotion, kick and move of a room in the order it happened.
+///
+/// Removed rooms can be looked up too, by their number or message ID.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    check = "is_host_or_mod",
+    default_member_permissions = "MANAGE_MESSAGES"
+)]
+pub async fn history(
+    ctx: Context<'_>,
+    #[description = "Room number or message ID for the room"]
+    #[autocomplete = "autocomplete_room"]
+    #[rest]
+    room: String,
+) -> Result<(), Error> {
     let guild_id = match ctx.guild_id() {
         Some(gid) => gid,
-        None => unreachable!(),
-    };
-
-    let desc_start = if let Some(link) = get_message_link(message_id.0, ctx.data(), guild_id) {
-        format!("**[Signups for Room #{}]({})**", room_num, link)
-    } else {
-        format!("**Signups for Room #{}**", room_num)
+        None => return Ok(()),
     };
 
-    if records.is_empty() {
-        embed.description(format!("{desc_start}\n\nNo signups yet."));
-    } else {
-        embed.description(desc_start);
-        let mut registered = String::new();
-        let mut reserves = String::new();
-
-        for (i, record) in records.iter().enumerate() {
-            if i < 9 {
-                registered.push_str(&format_user_str(record.user_id));
-            } else {
-                reserves.push_str(&format_user_str(record.user_id));
-            }
-        }
+    let pool = &ctx.data().db_pool;
+    let (message_id, room_num) = find_history_room(pool, &room, guild_id).await?;
 
-        embed.field(
-            format!("Registered ({}/9)", records.len().min(9)),
-            registered,
-            true,
-        );
-
-        if records.len() > 9 {
-            embed.field(
-                format!("Reserves ({}/6)", records.len() - 9),
-                reserves,
-                true,
-            );
-        }
+    let timeline = get_timeline(pool, message_id).await?;
+    if timeline.is_empty() {
+        ctx.say(format!("Room #{room_num} has no recorded signups yet."))
+            .await?;
+        return Ok(());
     }
 
-    ctx.send(|m| {
-        m.embed(|e| {
-            e.0 = embed.0;
-            e
+    for (i, page) in split_message(&timeline, "\n").into_iter().enumerate() {
+        ctx.send(|m| {
+            m.embed(|e| {
+                if i == 0 {
+                    e.title(format!("History of Room #{room_num}"));
+                }
+                e.description(page).colour(EMBED_COLOUR)
+            })
         })
-    })
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Changes the time, host, title or capacity of a room without losing its signups.
+///
+/// Use `none` as the title to remove a custom title.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    check = "is_host_or_mod",
+    default_member_permissions = "MANAGE_MESSAGES"
+)]
+pub async fn editroom(
+    ctx: Context<'_>,
+    #[description = "Room number or message ID for the room"]
+    #[autocomplete = "autocomplete_room"]
+    room: String,
+    #[description = "What to change: time, host, title or capacity"] field: RoomField,
+    #[description = "The new value"]
+    #[rest]
+    value: String,
+) -> Result<(), Error> {
+    let guild_id = match ctx.guild_id() {
+        Some(gid) => gid,
+        None => return Ok(()),
+    };
+
+    ctx.defer().await?;
+
+    let pool = &ctx.data().db_pool;
+    let message_id = get_message_id(&room, guild_id, pool).await?;
+
+    let record = sqlx::query!(
+        r#"SELECT num, main_slots, status as "status: RoomStatus",
+        (SELECT COUNT(*) FROM signup s WHERE s.message_id = m.message_id) as "signups!"
+        FROM message m WHERE message_id = $1"#,
+        message_id.0 as i64
+    )
+    .fetch_one(pool)
     .await?;
 
+    if matches!(record.status, RoomStatus::Finished | RoomStatus::Cancelled) {
+        return Err(format!(
+            "Room #{} is {} and can't be edited.",
+            record.num,
+            record.status.name()
+        )
+        .into());
+    }
+
+    let value = value.trim();
+
+    let reply = match field {
+        RoomField::Time => {
+            let starts_at = parse_datetime(value, chrono::Utc::now())?;
+
+            sqlx::query!(
+                "UPDATE message SET starts_at = $1 WHERE message_id = $2",
+                starts_at,
+                message_id.0 as i64
+            )
+            .execute(pool)
+            .await?;
+
+            schedule_room_jobs(pool, guild_id, message_id, starts_at).await?;
+
+            let content = format!(
+                "Room #{} has been moved to {}.",
+                record.num,
+                format_timestamp(starts_at)
+            );
+
+            let roster = get_roster(pool, message_id).await?;
+            let mut notified = 0;
+            for user_id in &roster {
+                if dm_user(
+                    ctx.serenity_context(),
+                    serenity::UserId(*user_id as u64),
+                    &content,
+                )
+                .await
+                .is_ok()
+                {
+                    notified += 1;
+                }
+            }
+
+            format!(
+                "Moved Room #{} to {} and notified {notified}/{} signed up players.",
+                record.num,
+                format_timestamp(starts_at),
+                roster.len()
+            )
+        },
+        RoomField::Host => {
+            let member = serenity::Member::convert(
+                ctx.serenity_context(),
+                Some(guild_id),
+                Some(ctx.channel_id()),
+                value,
+            )
+            .await
+            .map_err(|_| format!("Unable to find a member matching `{value}`."))?;
+
+            sqlx::query!(
+                "UPDATE message SET host_id = $1 WHERE message_id = $2",
+                member.user.id.0 as i64,
+                message_id.0 as i64
+            )
+            .execute(pool)
+            .await?;
+
+            format!("{} is now hosting Room #{}.", member.mention(), record.num)
+        },
+        RoomField::Title => {
+            let title = (!value.eq_ignore_ascii_case("none")).then_some(value);
+
+            sqlx::query!(
+                "UPDATE message SET title = $1 WHERE message_id = $2",
+                title,
+                message_id.0 as i64
+            )
+            .execute(pool)
+            .await?;
+
+            format!("Updated the title of Room #{}.", record.num)
+        },
+        RoomField::Capacity => {
+            let capacity = value.parse::<Capacity>()?;
+
+            if (capacity.total() as i64) < record.signups {
+                return Err(format!(
+                    "{} players already signed up for Room #{}, which is more than {} slots.",
+                    record.signups,
+                    record.num,
+                    capacity.total()
+                )
+                .into());
+            }
+
+            let before = get_roster(pool, message_id).await?;
+
+            sqlx::query!(
+                "UPDATE message SET main_slots = $1, reserve_slots = $2 WHERE message_id = $3",
+                capacity.main,
+                capacity.reserve,
+                message_id.0 as i64
+            )
+            .execute(pool)
+            .await?;
+
+            notify_roster_changes(
+                ctx.serenity_context(),
+                ctx.data(),
+                message_id,
+                &before,
+                record.main_slots,
+                Some(ctx.author().id),
+            )
+            .await?;
+            refresh_participant_role(ctx.serenity_context(), pool, message_id).await;
+
+            format!(
+                "Room #{} now has {} main slots and {} reserve slots.",
+                record.num, capacity.main, capacity.reserve
+            )
+        },
+    };
+
+    update_room_embed(ctx.serenity_context(), pool, message_id).await?;
+    update_panel(ctx.serenity_context(), pool, message_id).await?;
+
+    ctx.say(reply).await?;
+
     Ok(())
 }
 
 /// Sets the host role ID.
-#[poise::command(prefix_command, owners_only, guild_only)]
+#[poise::command(
+    prefix_command,
+    slash_command,
+    owners_only,
+    guild_only,
+    default_member_permissions = "MANAGE_GUILD"
+)]
 pub async fn sethost(
     ctx: Context<'_>,
-    #[description = "The host role id"] id: u64,
+    #[description = "The host role"] role: serenity::Role,
 ) -> Result<(), Error> {
+    let id = role.id.0;
     let guild_id = match ctx.guild_id() {
         Some(gid) => gid,
         None => return Ok(()),
@@ -190,190 +483,1423 @@ pub async fn sethost(
     .execute(&ctx.data().db_pool)
     .await?;
 
-    ctx.say("Set host role id.").await?;
+    ctx.say(format!("Set {} as the host role.", role.mention()))
+        .await?;
 
     Ok(())
 }
 
-/// Sets the fnf self roles channel ID.
-#[poise::command(prefix_command, owners_only, guild_only)]
-pub async fn fnfchannel(
+/// Sets the default capacity for new rooms, written as `main+reserve`.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    owners_only,
+    guild_only,
+    default_member_permissions = "MANAGE_GUILD"
+)]
+pub async fn setcapacity(
     ctx: Context<'_>,
-    #[description = "The fnf channel id"] channel_id: serenity::ChannelId,
+    #[description = "The default capacity, e.g. `9+6`"] capacity: Capacity,
 ) -> Result<(), Error> {
     let guild_id = match ctx.guild_id() {
         Some(gid) => gid,
         None => return Ok(()),
     };
 
-    // we try updating our local channel ids cache first intentionally
+    // we try updating our local capacity cache first intentionally
     let mut entry = ctx.data().guild_configs.entry(guild_id.0).or_default();
-    entry.channel_id = Some(channel_id.0);
+    entry.capacity = Some(capacity);
 
     sqlx::query!(
-        "INSERT INTO config (guild_id, fnf_channel_id) VALUES ($1, $2)
-        ON CONFLICT (guild_id) DO UPDATE SET fnf_channel_id = EXCLUDED.fnf_channel_id;",
+        "INSERT INTO config (guild_id, main_slots, reserve_slots) VALUES ($1, $2, $3)
+        ON CONFLICT (guild_id) DO UPDATE
+        SET main_slots = EXCLUDED.main_slots, reserve_slots = EXCLUDED.reserve_slots;",
         guild_id.0 as i64,
-        channel_id.0 as i64
+        capacity.main,
+        capacity.reserve,
     )
     .execute(&ctx.data().db_pool)
     .await?;
 
-    ctx.say(format!("Set {} as the fnf channel.", channel_id.mention()))
-        .await?;
-
-    Ok(())
-}
+    ctx.say(format!(
+        "New rooms will have {} main slots and {} reserve slots.",
+        capacity.main, capacity.reserve
+    ))
+    .await?;
 
-/// Shuts down the bot.
-#[poise::command(prefix_command, owners_only)]
-pub async fn shutdown(ctx: Context<'_>) -> Result<(), Error> {
-    ctx.say("Shutting down... 👋").await?;
-    ctx.framework()
-        .shard_manager()
-        .lock()
-        .await
-        .shutdown_all()
-        .await;
     Ok(())
 }
 
-/// Sends the bot's invite URL.
-#[poise::command(prefix_command, owners_only)]
-pub async fn invite(ctx: Context<'_>) -> Result<(), Error> {
-    let user = if let Some(cache) = ctx.cache() {
-        cache.current_user()
-    } else {
-        error!("unable to get cache");
-        return Err("unable to get cache".into());
+/// Sets how many active rooms a player may sign up for at once, `0` meaning unlimited.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    owners_only,
+    guild_only,
+    default_member_permissions = "MANAGE_GUILD"
+)]
+pub async fn setroomlimit(
+    ctx: Context<'_>,
+    #[description = "The number of rooms, or 0 for no limit"] limit: u16,
+) -> Result<(), Error> {
+    let guild_id = match ctx.guild_id() {
+        Some(gid) => gid,
+        None => return Ok(()),
     };
-    ctx.say(format!(
-        "You can invite the bot using the following URL: {}",
-        invite_url(&user, &ctx).await?,
-    ))
+
+    // we try updating our local room limit cache first intentionally
+    let mut entry = ctx.data().guild_configs.entry(guild_id.0).or_default();
+    entry.room_limit = Some(limit as i32);
+
+    sqlx::query!(
+        "INSERT INTO config (guild_id, room_limit) VALUES ($1, $2)
+        ON CONFLICT (guild_id) DO UPDATE SET room_limit = EXCLUDED.room_limit;",
+        guild_id.0 as i64,
+        limit as i32
+    )
+    .execute(&ctx.data().db_pool)
     .await?;
+
+    if limit == 0 {
+        ctx.say("Players may now sign up for any number of rooms.")
+            .await?;
+    } else {
+        ctx.say(format!(
+            "Players may now sign up for {limit} active room(s) at a time."
+        ))
+        .await?;
+    }
+
     Ok(())
 }
 
-/// Removes a room from the database.
-#[poise::command(prefix_command, check = "is_host_or_mod")]
-pub async fn remove(
+/// Sets whether players sign up for rooms through buttons or reactions.
+///
+/// Only affects rooms hosted afterwards.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    owners_only,
+    guild_only,
+    default_member_permissions = "MANAGE_GUILD"
+)]
+pub async fn signupmode(
     ctx: Context<'_>,
-    #[description = "Message ID for the room"] message_id: u64,
+    #[description = "Either buttons or reactions"] mode: SignupMode,
 ) -> Result<(), Error> {
+    let guild_id = match ctx.guild_id() {
+        Some(gid) => gid,
+        None => return Ok(()),
+    };
+
+    // we try updating our local signup mode cache first intentionally
+    let mut entry = ctx.data().guild_configs.entry(guild_id.0).or_default();
+    entry.signup_mode = Some(mode);
+
     sqlx::query!(
-        "DELETE FROM message WHERE message_id = $1",
-        message_id as i64
+        "INSERT INTO config (guild_id, signup_mode) VALUES ($1, $2)
+        ON CONFLICT (guild_id) DO UPDATE SET signup_mode = EXCLUDED.signup_mode;",
+        guild_id.0 as i64,
+        mode as SignupMode,
     )
     .execute(&ctx.data().db_pool)
     .await?;
 
-    // only remove from our local cache if database removal is successful
-    ctx.data().messages.remove(&message_id);
-
     ctx.say(format!(
-        "Removed the room associated with message {}",
-        message_id
+        "Players will sign up for new rooms using {mode}. Existing rooms are unaffected."
     ))
     .await?;
 
     Ok(())
 }
 
-/// Removes all rooms from the database.
-#[poise::command(prefix_command, owners_only)]
-pub async fn removeall(ctx: Context<'_>) -> Result<(), Error> {
-    ctx.say(
-        "This command is destructive and impacts multiple servers. \
-        If you would like to continue, enter **CONFIRM** as your next message.",
+/// Sets how many minutes before a room starts its players are reminded.
+///
+/// Only affects rooms hosted afterwards. Pass no offsets to disable reminders.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    owners_only,
+    guild_only,
+    default_member_permissions = "MANAGE_GUILD"
+)]
+pub async fn setreminders(
+    ctx: Context<'_>,
+    #[description = "Minutes before the start, e.g. `30 5`"]
+    #[rest]
+    offsets: Option<String>,
+) -> Result<(), Error> {
+    let guild_id = match ctx.guild_id() {
+        Some(gid) => gid,
+        None => return Ok(()),
+    };
+
+    let mut offsets = offsets
+        .as_deref()
+        .unwrap_or_default()
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|o| !o.is_empty())
+        .map(|o| o.parse::<u16>().map(i32::from))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| "Reminder offsets must be whole numbers of minutes, e.g. `30 5`.")?;
+    offsets.sort_unstable_by(|a, b| b.cmp(a));
+    offsets.dedup();
+
+    sqlx::query!(
+        "INSERT INTO config (guild_id, reminder_offsets) VALUES ($1, $2)
+        ON CONFLICT (guild_id) DO UPDATE SET reminder_offsets = EXCLUDED.reminder_offsets;",
+        guild_id.0 as i64,
+        &offsets
     )
+    .execute(&ctx.data().db_pool)
     .await?;
 
-    if !confirm_prompt(&ctx, 30.0, "CONFIRM").await {
-        ctx.say("Cancelled execution.").await?;
-        return Ok(());
-    }
-
-    sqlx::query("DELETE FROM message")
-        .execute(&ctx.data().db_pool)
+    if offsets.is_empty() {
+        ctx.say("Disabled reminders for new rooms.").await?;
+    } else {
+        let offsets = offsets
+            .iter()
+            .map(|m| format!("{m}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        ctx.say(format!(
+            "Players will be reminded {offsets} minutes before their room starts."
+        ))
         .await?;
-
-    ctx.say("Removed all rooms.").await?;
+    }
 
     Ok(())
 }
 
-/// Adds all players registered for the room to the specified channel.
-#[poise::command(prefix_command, check = "is_host_or_mod")]
-pub async fn addplayers(
+/// Sets whether reminders also ping the main roster in the fnf channel.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    owners_only,
+    guild_only,
+    default_member_permissions = "MANAGE_GUILD"
+)]
+pub async fn reminderping(
     ctx: Context<'_>,
-    #[description = "Room number or message ID for the room"] room: String,
-    #[description = "The channel to add the players to"]
-    #[rest]
-    channel_id: serenity::ChannelId,
+    #[description = "`true` to ping players, `false` to only DM them"] enabled: bool,
 ) -> Result<(), Error> {
-    let message_id = get_message_id(&room, &ctx.data().db_pool).await?;
+    let guild_id = match ctx.guild_id() {
+        Some(gid) => gid,
+        None => return Ok(()),
+    };
+
+    sqlx::query!(
+        "INSERT INTO config (guild_id, reminder_ping) VALUES ($1, $2)
+        ON CONFLICT (guild_id) DO UPDATE SET reminder_ping = EXCLUDED.reminder_ping;",
+        guild_id.0 as i64,
+        enabled
+    )
+    .execute(&ctx.data().db_pool)
+    .await?;
+
+    if enabled {
+        ctx.say("Reminders will also ping players in the fnf channel.")
+            .await?;
+    } else {
+        ctx.say("Reminders will only be sent as DMs.").await?;
+    }
+
+    Ok(())
+}
+
+/// Sets how many minutes before a room starts its registrations close automatically.
+///
+/// Only affects rooms hosted afterwards.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    owners_only,
+    guild_only,
+    default_member_permissions = "MANAGE_GUILD"
+)]
+pub async fn setautoclose(
+    ctx: Context<'_>,
+    #[description = "Minutes before the start, 0 to close when the room starts"] minutes: u16,
+) -> Result<(), Error> {
+    let guild_id = match ctx.guild_id() {
+        Some(gid) => gid,
+        None => return Ok(()),
+    };
+
+    sqlx::query!(
+        "INSERT INTO config (guild_id, close_lead_minutes) VALUES ($1, $2)
+        ON CONFLICT (guild_id) DO UPDATE SET close_lead_minutes = EXCLUDED.close_lead_minutes;",
+        guild_id.0 as i64,
+        minutes as i32
+    )
+    .execute(&ctx.data().db_pool)
+    .await?;
+
+    if minutes == 0 {
+        ctx.say("Registrations will close when a room starts.")
+            .await?;
+    } else {
+        ctx.say(format!(
+            "Registrations will close {minutes} minutes before a room starts."
+        ))
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Sets how many minutes after it starts a room is automatically marked as finished.
+///
+/// Only affects rooms hosted afterwards.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    owners_only,
+    guild_only,
+    default_member_permissions = "MANAGE_GUILD"
+)]
+pub async fn setduration(
+    ctx: Context<'_>,
+    #[description = "Length of a room in minutes, 0 to never finish automatically"] minutes: u16,
+) -> Result<(), Error> {
+    let guild_id = match ctx.guild_id() {
+        Some(gid) => gid,
+        None => return Ok(()),
+    };
+
+    sqlx::query!(
+        "INSERT INTO config (guild_id, room_duration_minutes) VALUES ($1, $2)
+        ON CONFLICT (guild_id) DO UPDATE SET room_duration_minutes = EXCLUDED.room_duration_minutes;",
+        guild_id.0 as i64,
+        minutes as i32
+    )
+    .execute(&ctx.data().db_pool)
+    .await?;
+
+    if minutes == 0 {
+        ctx.say("Rooms will no longer be marked as finished automatically.")
+            .await?;
+    } else {
+        ctx.say(format!(
+            "Rooms will be marked as finished {minutes} minutes after they start."
+        ))
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Sets the fnf self roles channel ID.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    owners_only,
+    guild_only,
+    default_member_permissions = "MANAGE_GUILD"
+)]
+pub async fn fnfchannel(
+    ctx: Context<'_>,
+    #[description = "The fnf channel"]
+    #[channel_types("Text")]
+    channel: serenity::GuildChannel,
+) -> Result<(), Error> {
+    let channel_id = channel.id;
+    let guild_id = match ctx.guild_id() {
+        Some(gid) => gid,
+        None => return Ok(()),
+    };
+
+    // we try updating our local channel ids cache first intentionally
+    let mut entry = ctx.data().guild_configs.entry(guild_id.0).or_default();
+    entry.channel_id = Some(channel_id.0);
+
+    sqlx::query!(
+        "INSERT INTO config (guild_id, fnf_channel_id) VALUES ($1, $2)
+        ON CONFLICT (guild_id) DO UPDATE SET fnf_channel_id = EXCLUDED.fnf_channel_id;",
+        guild_id.0 as i64,
+        channel_id.0 as i64
+    )
+    .execute(&ctx.data().db_pool)
+    .await?;
+
+    ctx.say(format!("Set {} as the fnf channel.", channel_id.mention()))
+        .await?;
+
+    Ok(())
+}
+
+/// Sets the channel room control panels are sent to.
+///
+/// Without a panel channel, panels are sent to the room's host in DMs.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    owners_only,
+    guild_only,
+    default_member_permissions = "MANAGE_GUILD"
+)]
+pub async fn panelchannel(
+    ctx: Context<'_>,
+    #[description = "The staff channel for panels, leave empty to DM hosts instead"]
+    #[channel_types("Text")]
+    channel: Option<serenity::GuildChannel>,
+) -> Result<(), Error> {
+    let guild_id = match ctx.guild_id() {
+        Some(gid) => gid,
+        None => return Ok(()),
+    };
+
+    let channel_id = channel.map(|c| c.id);
+
+    // we try updating our local panel channel cache first intentionally
+    let mut entry = ctx.data().guild_configs.entry(guild_id.0).or_default();
+    entry.panel_channel_id = channel_id.map(|c| c.0);
+
+    sqlx::query!(
+        "INSERT INTO config (guild_id, panel_channel_id) VALUES ($1, $2)
+        ON CONFLICT (guild_id) DO UPDATE SET panel_channel_id = EXCLUDED.panel_channel_id;",
+        guild_id.0 as i64,
+        channel_id.map(|c| c.0 as i64)
+    )
+    .execute(&ctx.data().db_pool)
+    .await?;
+
+    match channel_id {
+        Some(channel_id) => {
+            ctx.say(format!(
+                "Room control panels will be sent to {}.",
+                channel_id.mention()
+            ))
+            .await?
+        },
+        None => {
+            ctx.say("Room control panels will be sent to hosts in DMs.")
+                .await?
+        },
+    };
+
+    Ok(())
+}
+
+/// Sets the category private room channels are created in once a room closes.
+///
+/// Leave empty to stop creating room channels.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    owners_only,
+    guild_only,
+    default_member_permissions = "MANAGE_GUILD"
+)]
+pub async fn roomcategory(
+    ctx: Context<'_>,
+    #[description = "The category for room channels, leave empty to disable them"]
+    #[channel_types("Category")]
+    category: Option<serenity::GuildChannel>,
+) -> Result<(), Error> {
+    let guild_id = match ctx.guild_id() {
+        Some(gid) => gid,
+        None => return Ok(()),
+    };
+
+    let category_id = category.as_ref().map(|c| c.id);
+
+    sqlx::query!(
+        "INSERT INTO config (guild_id, room_category_id) VALUES ($1, $2)
+        ON CONFLICT (guild_id) DO UPDATE SET room_category_id = EXCLUDED.room_category_id;",
+        guild_id.0 as i64,
+        category_id.map(|c| c.0 as i64)
+    )
+    .execute(&ctx.data().db_pool)
+    .await?;
+
+    match category {
+        Some(category) => {
+            ctx.say(format!(
+                "Private channels for rooms will be created in the {} category.",
+                category.name
+            ))
+            .await?
+        },
+        None => {
+            ctx.say("Rooms will no longer get private channels.")
+                .await?
+        },
+    };
+
+    Ok(())
+}
+
+/// Sets whether rooms also get a private voice channel.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    owners_only,
+    guild_only,
+    default_member_permissions = "MANAGE_GUILD"
+)]
+pub async fn roomvoice(
+    ctx: Context<'_>,
+    #[description = "Whether to create a voice channel as well"] enabled: bool,
+) -> Result<(), Error> {
+    let guild_id = match ctx.guild_id() {
+        Some(gid) => gid,
+        None => return Ok(()),
+    };
+
+    sqlx::query!(
+        "INSERT INTO config (guild_id, room_voice) VALUES ($1, $2)
+        ON CONFLICT (guild_id) DO UPDATE SET room_voice = EXCLUDED.room_voice;",
+        guild_id.0 as i64,
+        enabled
+    )
+    .execute(&ctx.data().db_pool)
+    .await?;
+
+    if enabled {
+        ctx.say("Rooms will get a private voice channel as well.")
+            .await?;
+    } else {
+        ctx.say("Rooms will only get a private text channel.")
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Sets whether private room channels are archived or deleted once a room ends.
+///
+/// Archived channels are kept, but only staff can see them.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    owners_only,
+    guild_only,
+    default_member_permissions = "MANAGE_GUILD"
+)]
+pub async fn roomcleanup(
+    ctx: Context<'_>,
+    #[description = "Either archive or delete"] cleanup: ChannelCleanup,
+) -> Result<(), Error> {
+    let guild_id = match ctx.guild_id() {
+        Some(gid) => gid,
+        None => return Ok(()),
+    };
+
+    sqlx::query!(
+        "INSERT INTO config (guild_id, room_cleanup) VALUES ($1, $2)
+        ON CONFLICT (guild_id) DO UPDATE SET room_cleanup = EXCLUDED.room_cleanup;",
+        guild_id.0 as i64,
+        cleanup as ChannelCleanup,
+    )
+    .execute(&ctx.data().db_pool)
+    .await?;
+
+    let verb = match cleanup {
+        ChannelCleanup::Archive => "archived",
+        ChannelCleanup::Delete => "deleted",
+    };
+    ctx.say(format!("Room channels will be {verb} once a room ends."))
+        .await?;
+
+    Ok(())
+}
+
+/// Sets the role the main roster of a room is given once registration closes.
+///
+/// The role is taken away again when the room ends. Leave empty to stop handing it out.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    owners_only,
+    guild_only,
+    default_member_permissions = "MANAGE_GUILD"
+)]
+pub async fn participantrole(
+    ctx: Context<'_>,
+    #[description = "The role for room players, leave empty to disable it"] role: Option<
+        serenity::Role,
+    >,
+) -> Result<(), Error> {
+    let guild_id = match ctx.guild_id() {
+        Some(gid) => gid,
+        None => return Ok(()),
+    };
+
+    sqlx::query!(
+        "INSERT INTO config (guild_id, participant_role_id) VALUES ($1, $2)
+        ON CONFLICT (guild_id) DO UPDATE SET participant_role_id = EXCLUDED.participant_role_id;",
+        guild_id.0 as i64,
+        role.as_ref().map(|r| r.id.0 as i64)
+    )
+    .execute(&ctx.data().db_pool)
+    .await?;
+
+    match role {
+        Some(role) => {
+            ctx.say(format!(
+                "Main roster players will get {} once registration closes.",
+                role.mention()
+            ))
+            .await?
+        },
+        None => {
+            ctx.say("Players will no longer get a participant role.")
+                .await?
+        },
+    };
+
+    Ok(())
+}
+
+/// Shuts down the bot.
+#[poise::command(prefix_command, owners_only)]
+pub async fn shutdown(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.say("Shutting down... 👋").await?;
+    ctx.framework()
+        .shard_manager()
+        .lock()
+        .await
+        .shutdown_all()
+        .await;
+    Ok(())
+}
+
+/// Sends the bot's invite URL.
+#[poise::command(prefix_command, owners_only)]
+pub async fn invite(ctx: Context<'_>) -> Result<(), Error> {
+    let user = if let Some(cache) = ctx.cache() {
+        cache.current_user()
+    } else {
+        error!("unable to get cache");
+        return Err("unable to get cache".into());
+    };
+    ctx.say(format!(
+        "You can invite the bot using the following URL: {}",
+        invite_url(&user, &ctx).await?,
+    ))
+    .await?;
+    Ok(())
+}
+
+/// Removes a room from the database.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    check = "is_host_or_mod",
+    default_member_permissions = "MANAGE_MESSAGES"
+)]
+pub async fn remove(
+    ctx: Context<'_>,
+    #[description = "Room number or message ID for the room"]
+    #[autocomplete = "autocomplete_room"]
+    #[rest]
+    room: String,
+) -> Result<(), Error> {
+    let guild_id = match ctx.guild_id() {
+        Some(gid) => gid,
+        None => return Ok(()),
+    };
+
+    let message_id = get_message_id(&room, guild_id, &ctx.data().db_pool).await?;
+
+    delete_room(ctx.serenity_context(), ctx.data(), message_id).await?;
+
+    ctx.say(format!(
+        "Removed the room associated with message {}",
+        message_id
+    ))
+    .await?;
+
+    Ok(())
+}
+
+/// Removes all rooms in this server from the database.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    owners_only,
+    guild_only,
+    default_member_permissions = "MANAGE_GUILD"
+)]
+pub async fn removeall(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = match ctx.guild_id() {
+        Some(gid) => gid,
+        None => return Ok(()),
+    };
+
+    ctx.say(
+        "This command is destructive and removes every room in this server. \
+        If you would like to continue, enter **CONFIRM** as your next message.",
+    )
+    .await?;
+
+    if !confirm_prompt(&ctx, 30.0, "CONFIRM").await {
+        ctx.say("Cancelled execution.").await?;
+        return Ok(());
+    }
+
+    let records = sqlx::query!(
+        "SELECT message_id, num FROM message WHERE guild_id = $1 ORDER BY num",
+        guild_id.0 as i64
+    )
+    .fetch_all(&ctx.data().db_pool)
+    .await?;
+
+    // one room that can't be cleaned up shouldn't keep the others around
+    let mut failed = Vec::new();
+    for record in records {
+        let message_id = serenity::MessageId(record.message_id as u64);
+        if let Err(e) = delete_room(ctx.serenity_context(), ctx.data(), message_id).await {
+            error!("error removing room {}: {e}", message_id.0);
+            failed.push(format!("Room #{}: {e}", record.num));
+        }
+    }
+
+    if failed.is_empty() {
+        ctx.say("Removed all rooms.").await?;
+        return Ok(());
+    }
+
+    let mut lines = vec![format!(
+        "Removed all rooms except {}, which can be removed again once fixed:",
+        failed.len()
+    )];
+    lines.extend(failed);
+    for content in split_message(&lines, "\n") {
+        ctx.say(content).await?;
+    }
+
+    Ok(())
+}
+
+/// Adds players registered for the room to the specified channel.
+///
+/// Adds the main roster and reserves by default. Use the dry run option to see who would be
+/// added without changing anything.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    check = "is_host_or_mod",
+    default_member_permissions = "MANAGE_MESSAGES"
+)]
+pub async fn addplayers(
+    ctx: Context<'_>,
+    #[description = "Room number or message ID for the room"]
+    #[autocomplete = "autocomplete_room"]
+    room: String,
+    #[description = "The channel to add the players to"] channel: serenity::GuildChannel,
+    #[description = "Which players to add, both main roster and reserves by default"]
+    players: Option<RosterGroup>,
+    #[description = "Only show who would be added"] dry_run: Option<bool>,
+) -> Result<(), Error> {
+    let channel_id = channel.id;
+    let guild_id = match ctx.guild_id() {
+        Some(gid) => gid,
+        None => return Ok(()),
+    };
+
+    ctx.defer().await?;
+
+    let message_id = get_message_id(&room, guild_id, &ctx.data().db_pool).await?;
+
+    let text = add_players(
+        ctx.serenity_context(),
+        &ctx.data().db_pool,
+        message_id,
+        channel_id,
+        players.unwrap_or(RosterGroup::Both),
+        dry_run.unwrap_or(false),
+        Some(ctx.channel_id()),
+    )
+    .await?;
+
+    ctx.say(text).await?;
+
+    Ok(())
+}
+
+/// Takes back the channel access `addplayers` gave a room's players.
+///
+/// Overwrites set by staff are left alone.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    check = "is_host_or_mod",
+    default_member_permissions = "MANAGE_MESSAGES"
+)]
+pub async fn removeplayers(
+    ctx: Context<'_>,
+    #[description = "Room number or message ID for the room"]
+    #[autocomplete = "autocomplete_room"]
+    room: String,
+    #[description = "The channel to remove the players from"]
+    #[rest]
+    channel: serenity::GuildChannel,
+) -> Result<(), Error> {
+    let guild_id = match ctx.guild_id() {
+        Some(gid) => gid,
+        None => return Ok(()),
+    };
+
+    ctx.defer().await?;
+
+    let message_id = get_message_id(&room, guild_id, &ctx.data().db_pool).await?;
+
+    let text = remove_players(
+        ctx.serenity_context(),
+        &ctx.data().db_pool,
+        message_id,
+        channel.id,
+        Some(ctx.channel_id()),
+    )
+    .await?;
+
+    ctx.say(text).await?;
+
+    Ok(())
+}
+
+/// Sends a fresh control panel for a room, replacing its old one.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    check = "is_host_or_mod",
+    default_member_permissions = "MANAGE_MESSAGES"
+)]
+pub async fn panel(
+    ctx: Context<'_>,
+    #[description = "Room number or message ID for the room"]
+    #[autocomplete = "autocomplete_room"]
+    #[rest]
+    room: String,
+) -> Result<(), Error> {
+    let guild_id = match ctx.guild_id() {
+        Some(gid) => gid,
+        None => return Ok(()),
+    };
+
+    let message_id = get_message_id(&room, guild_id, &ctx.data().db_pool).await?;
+    send_panel(ctx.serenity_context(), ctx.data(), message_id).await?;
+
+    ctx.say("Sent the room's control panel.").await?;
+
+    Ok(())
+}
+
+/// Signs a member up for a room, even if its registrations are closed.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    check = "is_host_or_mod",
+    default_member_permissions = "MANAGE_MESSAGES"
+)]
+pub async fn addplayer(
+    ctx: Context<'_>,
+    #[description = "Room number or message ID for the room"]
+    #[autocomplete = "autocomplete_room"]
+    room: String,
+    #[description = "The member to sign up"] member: serenity::Member,
+) -> Result<(), Error> {
+    let guild_id = match ctx.guild_id() {
+        Some(gid) => gid,
+        None => return Ok(()),
+    };
 
-    let records = sqlx::query!(
-        "SELECT user_id FROM signup WHERE message_id = $1",
-        message_id.0 as i64
+    let message_id = get_message_id(&room, guild_id, &ctx.data().db_pool).await?;
+    let text = add_to_room(ctx, guild_id, message_id, &member.user).await?;
+
+    ctx.say(text).await?;
+
+    Ok(())
+}
+
+/// Removes a player from a room, DMing them the reason.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    check = "is_host_or_mod",
+    default_member_permissions = "MANAGE_MESSAGES"
+)]
+pub async fn kick(
+    ctx: Context<'_>,
+    #[description = "Room number or message ID for the room"]
+    #[autocomplete = "autocomplete_room"]
+    room: String,
+    #[description = "The player to remove"] user: serenity::User,
+    #[description = "Optional reason sent to the player"]
+    #[rest]
+    reason: Option<String>,
+) -> Result<(), Error> {
+    let guild_id = match ctx.guild_id() {
+        Some(gid) => gid,
+        None => return Ok(()),
+    };
+
+    let message_id = get_message_id(&room, guild_id, &ctx.data().db_pool).await?;
+    let text = kick_player(ctx, message_id, &user, reason.as_deref()).await?;
+
+    ctx.say(text).await?;
+
+    Ok(())
+}
+
+/// Swaps the roster positions of two players, e.g. to move a reserve into the main roster.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    check = "is_host_or_mod",
+    default_member_permissions = "MANAGE_MESSAGES"
+)]
+pub async fn swap(
+    ctx: Context<'_>,
+    #[description = "Room number or message ID for the room"]
+    #[autocomplete = "autocomplete_room"]
+    room: String,
+    #[description = "One of the players"] first: serenity::User,
+    #[description = "The player to swap them with"] second: serenity::User,
+) -> Result<(), Error> {
+    let guild_id = match ctx.guild_id() {
+        Some(gid) => gid,
+        None => return Ok(()),
+    };
+
+    let pool = &ctx.data().db_pool;
+    let message_id = get_message_id(&room, guild_id, pool).await?;
+    let room_num = check_roster_editable(pool, message_id).await?;
+
+    let roster = get_roster(pool, message_id).await?;
+    let capacity = get_room_capacity(message_id, pool).await?;
+
+    swap_players(pool, message_id, first.id, second.id).await?;
+    refresh_participant_role(ctx.serenity_context(), pool, message_id).await;
+
+    notify_roster_changes(
+        ctx.serenity_context(),
+        ctx.data(),
+        message_id,
+        &roster,
+        capacity.main,
+        Some(ctx.author().id),
     )
-    .fetch_all(&ctx.data().db_pool)
     .await?;
+    update_room_embed(ctx.serenity_context(), pool, message_id).await?;
+
+    ctx.say(format!(
+        "Swapped {} and {} in Room #{room_num}.",
+        first.mention(),
+        second.mention()
+    ))
+    .await?;
+
+    Ok(())
+}
 
-    let allow =
-        Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES | Permissions::READ_MESSAGE_HISTORY;
+/// Moves a player from one room to the end of another room's roster.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    check = "is_host_or_mod",
+    default_member_permissions = "MANAGE_MESSAGES"
+)]
+pub async fn moveplayer(
+    ctx: Context<'_>,
+    #[description = "The player to move"] user: serenity::User,
+    #[description = "Room number or message ID of the room they are in"]
+    #[autocomplete = "autocomplete_room"]
+    from: String,
+    #[description = "Room number or message ID of the room to move them to"]
+    #[autocomplete = "autocomplete_room"]
+    to: String,
+) -> Result<(), Error> {
+    let guild_id = match ctx.guild_id() {
+        Some(gid) => gid,
+        None => return Ok(()),
+    };
 
-    let mut text = String::new();
+    let pool = &ctx.data().db_pool;
+    let from = get_message_id(&from, guild_id, pool).await?;
+    let to = get_message_id(&to, guild_id, pool).await?;
 
-    for record in records {
-        if let Err(e) = channel_id
-            .create_permission(&ctx, &PermissionOverwrite {
-                allow,
-                deny: Permissions::empty(),
-                kind: PermissionOverwriteType::Member(serenity::UserId(record.user_id as u64)),
-            })
-            .await
-        {
-            text.push_str(format!("Failed to add user <@{}>: {}\n", record.user_id, e).as_str());
-        } else {
-            text.push_str(format!("Added user <@{}>", record.user_id).as_str());
-        }
+    if from == to {
+        return Err("The player is already in that room.".into());
     }
 
-    ctx.say(text).await?;
+    let from_num = check_roster_editable(pool, from).await?;
+    let to_num = check_roster_editable(pool, to).await?;
+
+    let roster = get_roster(pool, from).await?;
+    let capacity = get_room_capacity(from, pool).await?;
+    let limit = get_room_limit(ctx.data(), guild_id);
+
+    let spot = match move_player(pool, guild_id, from, to, user.id, limit, ctx.author().id).await? {
+        Registration::Registered { position, capacity } => roster_spot(position, capacity),
+        refused => return Err(refusal_reason(&refused, &user, to_num).into()),
+    };
+
+    notify_roster_changes(
+        ctx.serenity_context(),
+        ctx.data(),
+        from,
+        &roster,
+        capacity.main,
+        Some(ctx.author().id),
+    )
+    .await?;
+    update_room_embed(ctx.serenity_context(), pool, from).await?;
+    update_room_embed(ctx.serenity_context(), pool, to).await?;
+    grant_channel_access(ctx.serenity_context(), pool, to, user.id).await?;
+    revoke_channel_access(ctx.serenity_context(), pool, from, user.id).await?;
+    refresh_participant_role(ctx.serenity_context(), pool, from).await;
+    refresh_participant_role(ctx.serenity_context(), pool, to).await;
+
+    // they are moved either way, closed dms only mean they find out from the embed
+    let _ = dm_user(
+        ctx.serenity_context(),
+        user.id,
+        format!("A host moved you from Room #{from_num} to Room #{to_num}{spot}."),
+    )
+    .await;
+
+    ctx.say(format!(
+        "Moved {} from Room #{from_num} to Room #{to_num}{spot}.",
+        user.mention()
+    ))
+    .await?;
 
     Ok(())
 }
 
-/// Returns true if user is a host or a moderator
-async fn is_host_or_mod(ctx: Context<'_>) -> Result<bool, Error> {
+/// Removes a player from the only room they are signed up for.
+#[poise::command(
+    context_menu_command = "Kick from room",
+    guild_only,
+    check = "is_host_or_mod",
+    default_member_permissions = "MANAGE_MESSAGES"
+)]
+pub async fn kick_menu(
+    ctx: Context<'_>,
+    #[description = "The player to remove"] user: serenity::User,
+) -> Result<(), Error> {
     let guild_id = match ctx.guild_id() {
         Some(gid) => gid,
-        None => return Ok(false),
+        None => return Ok(()),
     };
 
-    let member = guild_id.member(&ctx, ctx.author().id).await?;
-    if let Ok(permissions) = member.permissions(ctx) {
-        if permissions.contains(serenity::Permissions::BAN_MEMBERS) {
-            return Ok(true);
-        }
-    }
+    let rooms = sqlx::query!(
+        "SELECT m.message_id, m.num FROM signup s JOIN message m ON s.message_id = m.message_id
+        WHERE m.guild_id = $1 AND s.user_id = $2 AND m.status IN ('open', 'closed')
+        ORDER BY m.num",
+        guild_id.0 as i64,
+        user.id.0 as i64,
+    )
+    .fetch_all(&ctx.data().db_pool)
+    .await?;
+
+    let message_id = match rooms.as_slice() {
+        [] => return Err(format!("{} isn't signed up for any room.", user.mention()).into()),
+        [room] => serenity::MessageId(room.message_id as u64),
+        _ => {
+            let nums = rooms
+                .iter()
+                .map(|r| format!("#{}", r.num))
+                .collect::<Vec<_>>()
+                .join(", ");
+            return Err(format!(
+                "{} is signed up for rooms {nums}, use `/kick` to pick one.",
+                user.mention()
+            )
+            .into());
+        },
+    };
+
+    let text = kick_player(ctx, message_id, &user, None).await?;
+    ctx.send(|m| m.content(text).ephemeral(true)).await?;
+
+    Ok(())
+}
+
+/// Signs a member up for the newest open room.
+#[poise::command(
+    context_menu_command = "Add to newest room",
+    guild_only,
+    check = "is_host_or_mod",
+    default_member_permissions = "MANAGE_MESSAGES"
+)]
+pub async fn add_menu(
+    ctx: Context<'_>,
+    #[description = "The member to sign up"] user: serenity::User,
+) -> Result<(), Error> {
+    let guild_id = match ctx.guild_id() {
+        Some(gid) => gid,
+        None => return Ok(()),
+    };
 
-    let host_role_id = if let Some(GuildConfig {
-        channel_id: _,
-        host_id: Some(id),
-    }) = ctx.data().guild_configs.get(&guild_id.0).map(|c| *c)
+    let message_id = match sqlx::query!(
+        "SELECT message_id FROM message WHERE guild_id = $1 AND status = 'open'
+        ORDER BY num DESC LIMIT 1",
+        guild_id.0 as i64
+    )
+    .fetch_optional(&ctx.data().db_pool)
+    .await?
     {
-        id
+        Some(r) => serenity::MessageId(r.message_id as u64),
+        None => return Err("There is no open room to add players to.".into()),
+    };
+
+    let text = add_to_room(ctx, guild_id, message_id, &user).await?;
+    ctx.send(|m| m.content(text).ephemeral(true)).await?;
+
+    Ok(())
+}
+
+/// Reopens registrations for a room.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    check = "is_host_or_mod",
+    default_member_permissions = "MANAGE_MESSAGES"
+)]
+pub async fn open(
+    ctx: Context<'_>,
+    #[description = "Room number or message ID for the room"]
+    #[autocomplete = "autocomplete_room"]
+    #[rest]
+    room: String,
+) -> Result<(), Error> {
+    set_room_status(ctx, &room, RoomStatus::Open).await
+}
+
+/// Closes registrations for a room.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    check = "is_host_or_mod",
+    default_member_permissions = "MANAGE_MESSAGES"
+)]
+pub async fn close(
+    ctx: Context<'_>,
+    #[description = "Room number or message ID for the room"]
+    #[autocomplete = "autocomplete_room"]
+    #[rest]
+    room: String,
+) -> Result<(), Error> {
+    set_room_status(ctx, &room, RoomStatus::Closed).await
+}
+
+/// Marks a room as started, which also closes registrations.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    check = "is_host_or_mod",
+    default_member_permissions = "MANAGE_MESSAGES"
+)]
+pub async fn start(
+    ctx: Context<'_>,
+    #[description = "Room number or message ID for the room"]
+    #[autocomplete = "autocomplete_room"]
+    #[rest]
+    room: String,
+) -> Result<(), Error> {
+    set_room_status(ctx, &room, RoomStatus::Started).await
+}
+
+/// Marks a room as finished.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    check = "is_host_or_mod",
+    default_member_permissions = "MANAGE_MESSAGES"
+)]
+pub async fn finish(
+    ctx: Context<'_>,
+    #[description = "Room number or message ID for the room"]
+    #[autocomplete = "autocomplete_room"]
+    #[rest]
+    room: String,
+) -> Result<(), Error> {
+    set_room_status(ctx, &room, RoomStatus::Finished).await
+}
+
+/// Cancels a room and lets everyone who signed up know.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    check = "is_host_or_mod",
+    default_member_permissions = "MANAGE_MESSAGES"
+)]
+pub async fn cancel(
+    ctx: Context<'_>,
+    #[description = "Room number or message ID for the room"]
+    #[autocomplete = "autocomplete_room"]
+    room: String,
+    #[description = "Optional reason shown to players"]
+    #[rest]
+    reason: Option<String>,
+) -> Result<(), Error> {
+    let guild_id = match ctx.guild_id() {
+        Some(gid) => gid,
+        None => return Ok(()),
+    };
+
+    ctx.defer().await?;
+
+    let pool = &ctx.data().db_pool;
+    let message_id = get_message_id(&room, guild_id, pool).await?;
+
+    ctx.say(cancel_room(ctx.serenity_context(), pool, message_id, reason).await?)
+        .await?;
+
+    Ok(())
+}
+
+async fn set_room_status(ctx: Context<'_>, room: &str, next: RoomStatus) -> Result<(), Error> {
+    let guild_id = match ctx.guild_id() {
+        Some(gid) => gid,
+        None => return Ok(()),
+    };
+
+    let message_id = get_message_id(room, guild_id, &ctx.data().db_pool).await?;
+
+    let text = change_status(
+        ctx.serenity_context(),
+        &ctx.data().db_pool,
+        message_id,
+        next,
+    )
+    .await?;
+
+    ctx.say(text).await?;
+
+    Ok(())
+}
+
+/// Returns the number of the room if hosts may still change its roster.
+async fn check_roster_editable(
+    pool: &sqlx::PgPool,
+    message_id: serenity::MessageId,
+) -> Result<i32, Error> {
+    let record = sqlx::query!(
+        r#"SELECT num, status as "status: RoomStatus" FROM message WHERE message_id = $1"#,
+        message_id.0 as i64
+    )
+    .fetch_one(pool)
+    .await?;
+
+    if !record.status.accepts_withdrawals() {
+        return Err(format!(
+            "Room #{} is {}, so its roster can't be changed anymore.",
+            record.num,
+            record.status.name()
+        )
+        .into());
+    }
+
+    Ok(record.num)
+}
+
+/// Describes a roster position for messages like "added to Room #1 as reserve 2/6".
+fn roster_spot(position: i64, capacity: Capacity) -> String {
+    let main = capacity.main as i64;
+    if position < main {
+        String::new()
     } else {
-        return Err("The host role is not set.".into());
+        format!(" as reserve {}/{}", position - main + 1, capacity.reserve)
+    }
+}
+
+/// Explains to a host why a player couldn't be signed up for a room.
+fn refusal_reason(registration: &Registration, user: &serenity::User, room_num: i32) -> String {
+    match registration {
+        Registration::Registered { .. } => {
+            format!("{} is signed up for Room #{room_num}.", user.mention())
+        },
+        Registration::Closed => format!("Room #{room_num} doesn't take players anymore."),
+        Registration::AlreadyRegistered => {
+            format!(
+                "{} is already signed up for Room #{room_num}.",
+                user.mention()
+            )
+        },
+        Registration::LimitReached(rooms) => format!(
+            "{} is already signed up for as many rooms as allowed: {}.",
+            user.mention(),
+            rooms
+                .iter()
+                .map(|num| format!("#{num}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Registration::Full => format!("Room #{room_num} is full."),
+    }
+}
+
+/// Signs a player up for a room on behalf of a host and lets them know.
+async fn add_to_room(
+    ctx: Context<'_>,
+    guild_id: serenity::GuildId,
+    message_id: serenity::MessageId,
+    user: &serenity::User,
+) -> Result<String, Error> {
+    let pool = &ctx.data().db_pool;
+    let room_num = check_roster_editable(pool, message_id).await?;
+    let limit = get_room_limit(ctx.data(), guild_id);
+
+    let spot = match register_player(
+        pool,
+        guild_id,
+        message_id,
+        user.id,
+        limit,
+        true,
+        ctx.author().id,
+    )
+    .await?
+    {
+        Registration::Registered { position, capacity } => roster_spot(position, capacity),
+        refused => return Err(refusal_reason(&refused, user, room_num).into()),
+    };
+
+    update_room_embed(ctx.serenity_context(), pool, message_id).await?;
+    grant_channel_access(ctx.serenity_context(), pool, message_id, user.id).await?;
+    refresh_participant_role(ctx.serenity_context(), pool, message_id).await;
+
+    // they are signed up either way, closed dms only mean they find out from the embed
+    let _ = dm_user(
+        ctx.serenity_context(),
+        user.id,
+        format!("A host signed you up for Room #{room_num}{spot}."),
+    )
+    .await;
+
+    Ok(format!(
+        "Signed {} up for Room #{room_num}{spot}.",
+        user.mention()
+    ))
+}
+
+/// Removes a player from a room on behalf of a host and DMs them the reason.
+async fn kick_player(
+    ctx: Context<'_>,
+    message_id: serenity::MessageId,
+    user: &serenity::User,
+    reason: Option<&str>,
+) -> Result<String, Error> {
+    let pool = &ctx.data().db_pool;
+    let room_num = check_roster_editable(pool, message_id).await?;
+
+    let roster = get_roster(pool, message_id).await?;
+    let capacity = get_room_capacity(message_id, pool).await?;
+
+    if !withdraw_player(
+        pool,
+        message_id,
+        user.id,
+        SignupEventKind::Kick,
+        Some(ctx.author().id),
+        reason,
+        false,
+    )
+    .await?
+    {
+        return Err(format!("{} isn't signed up for Room #{room_num}.", user.mention()).into());
+    }
+
+    revoke_channel_access(ctx.serenity_context(), pool, message_id, user.id).await?;
+    refresh_participant_role(ctx.serenity_context(), pool, message_id).await;
+
+    notify_roster_changes(
+        ctx.serenity_context(),
+        ctx.data(),
+        message_id,
+        &roster,
+        capacity.main,
+        Some(ctx.author().id),
+    )
+    .await?;
+    update_room_embed(ctx.serenity_context(), pool, message_id).await?;
+
+    let content = match reason {
+        Some(reason) => format!("A host removed you from Room #{room_num}: {reason}"),
+        None => format!("A host removed you from Room #{room_num}."),
+    };
+
+    if dm_user(ctx.serenity_context(), user.id, content)
+        .await
+        .is_err()
+    {
+        return Ok(format!(
+            "Removed {} from Room #{room_num}, but couldn't DM them.",
+            user.mention()
+        ));
+    }
+
+    Ok(format!("Removed {} from Room #{room_num}.", user.mention()))
+}
+
+/// Suggests the invoking guild's rooms that haven't finished or been cancelled yet.
+async fn autocomplete_room(
+    ctx: Context<'_>,
+    partial: &str,
+) -> Vec<poise::AutocompleteChoice<String>> {
+    let guild_id = match ctx.guild_id() {
+        Some(gid) => gid,
+        None => return Vec::new(),
+    };
+
+    let records = match sqlx::query!(
+        r#"SELECT num, status as "status: RoomStatus" FROM message
+        WHERE guild_id = $1 AND status IN ('open', 'closed', 'started')
+            AND num::text LIKE $2 || '%'
+        ORDER BY num DESC
+        LIMIT 25"#,
+        guild_id.0 as i64,
+        partial.trim().trim_start_matches('#'),
+    )
+    .fetch_all(&ctx.data().db_pool)
+    .await
+    {
+        Ok(r) => r,
+        Err(e) => {
+            error!("error autocompleting rooms: {e}");
+            return Vec::new();
+        },
     };
 
-    // we don't want to show any specific error in this case
-    Ok(member.roles.contains(&host_role_id.into()))
+    records
+        .into_iter()
+        .map(|r| poise::AutocompleteChoice {
+            name: format!("Room #{} ({})", r.num, r.status.name()),
+            value: r.num.to_string(),
+        })
+        .collect()
 }
 
-fn format_user_str(uid: i64) -> String {
-    format!("<@{uid}> ({uid})\n")
+/// Returns true if user is a host or a moderator
+async fn is_host_or_mod(ctx: Context<'_>) -> Result<bool, Error> {
+    let guild_id = match ctx.guild_id() {
+        Some(gid) => gid,
+        None => return Ok(false),
+    };
+
+    can_manage_rooms(
+        ctx.serenity_context(),
+        ctx.data(),
+        guild_id,
+        ctx.author().id,
+    )
+    .await
 }
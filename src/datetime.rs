@@ -0,0 +1,476 @@
+use chrono::{
+    DateTime,
+    Datelike,
+    Duration,
+    FixedOffset,
+    Month,
+    NaiveDate,
+    NaiveTime,
+    TimeZone,
+    Utc,
+    Weekday,
+};
+
+use crate::Error;
+
+/// How many days ahead rooms can be hosted. Anything later is almost certainly a typo, such
+/// as a timestamp in the wrong unit.
+const MAX_LEAD_DAYS: i64 = 366;
+
+const FORMAT_HELP: &str = "Try something like `tomorrow 8pm`, `friday 19:30 UTC+2`, \
+    `2026-10-20 19:00 UTC` or `in 2h`. Times without a timezone are read as UTC.";
+
+/// Parses a room start time written in plain English, such as `tomorrow 8pm`,
+/// `2026-10-20 19:00 UTC` or `in 2h`, relative to `now`.
+pub fn parse_datetime(input: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>, Error> {
+    let normalized = input.trim().to_lowercase();
+
+    let parsed = parse_timestamp(input.trim())
+        .or_else(|| parse_relative(&normalized, now))
+        .or_else(|| parse_absolute(&normalized, now))
+        .ok_or_else(|| {
+            format!(
+                "Unable to understand `{}` as a date and time. {FORMAT_HELP}",
+                input.trim()
+            )
+        })?;
+
+    if parsed <= now {
+        return Err(format!("{} is in the past. {FORMAT_HELP}", format_timestamp(parsed)).into());
+    }
+
+    if parsed - now > Duration::days(MAX_LEAD_DAYS) {
+        return Err(format!(
+            "{} is too far in the future. {FORMAT_HELP}",
+            format_timestamp(parsed)
+        )
+        .into());
+    }
+
+    Ok(parsed)
+}
+
+/// Formats a timestamp using Discord's timestamp tags, so every member sees it in their
+/// own timezone.
+pub fn format_timestamp(dt: DateTime<Utc>) -> String {
+    let ts = dt.timestamp();
+    format!("<t:{ts}:F> (<t:{ts}:R>)")
+}
+
+/// Parses RFC 3339 datetimes, unix timestamps in seconds or milliseconds and Discord
+/// timestamp tags.
+fn parse_timestamp(input: &str) -> Option<DateTime<Utc>> {
+    if let Ok(dt) = DateTime::parse_from_rfc3339(input) {
+        return Some(dt.with_timezone(&Utc));
+    }
+
+    let ts = input
+        .strip_prefix("<t:")
+        .and_then(|s| s.strip_suffix('>'))
+        .map(|s| s.split(':').next().unwrap_or(s))
+        .unwrap_or(input);
+
+    // short numbers are much more likely to be a typo than a timestamp. javascript and many
+    // other tools hand out milliseconds, which are 13 digits long until the year 2286
+    let ts = ts.parse::<i64>().ok()?;
+    match ts.to_string().len() {
+        9..=11 => Utc.timestamp_opt(ts, 0).single(),
+        13 => Utc.timestamp_millis_opt(ts).single(),
+        _ => None,
+    }
+}
+
+/// Parses durations relative to now, e.g. `in 2h`, `in 1h30m` or `in 2 hours and 15 minutes`.
+fn parse_relative(input: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let rest = input.strip_prefix("in ")?;
+
+    // split into runs of digits and letters, so `1h30m` and `1 h 30 m` look the same
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    for c in rest.chars() {
+        if c.is_whitespace() || c == ',' {
+            tokens.push(std::mem::take(&mut current));
+            continue;
+        }
+        if current
+            .chars()
+            .last()
+            .is_some_and(|l| l.is_ascii_digit() != c.is_ascii_digit())
+        {
+            tokens.push(std::mem::take(&mut current));
+        }
+        current.push(c);
+    }
+    tokens.push(current);
+
+    let mut tokens = tokens.into_iter().filter(|t| !t.is_empty() && t != "and");
+    let mut total = Duration::zero();
+    let mut any = false;
+
+    while let Some(amount) = tokens.next() {
+        let amount = match amount.as_str() {
+            "a" | "an" => 1,
+            n => n.parse::<u32>().ok()? as i64,
+        };
+
+        let duration = match tokens.next()?.as_str() {
+            "m" | "min" | "mins" | "minute" | "minutes" => Duration::minutes(amount),
+            "h" | "hr" | "hrs" | "hour" | "hours" => Duration::hours(amount),
+            "d" | "day" | "days" => Duration::days(amount),
+            "w" | "week" | "weeks" => Duration::weeks(amount),
+            _ => return None,
+        };
+
+        total = total.checked_add(&duration)?;
+        any = true;
+    }
+
+    if any {
+        now.checked_add_signed(total)
+    } else {
+        None
+    }
+}
+
+/// Parses a calendar date and/or time of day with an optional trailing timezone, e.g.
+/// `tomorrow at 8pm`, `next friday 19:30 utc+2` or `oct 20 8:30 pm`.
+fn parse_absolute(input: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let mut words = input
+        .split_whitespace()
+        .filter(|w| !matches!(*w, "at" | "on"))
+        .collect::<Vec<_>>();
+
+    let offset = match words.last().and_then(|w| parse_offset(w)) {
+        Some(offset) => {
+            words.pop();
+            offset
+        },
+        None => FixedOffset::east_opt(0)?,
+    };
+
+    let local_now = now.with_timezone(&offset);
+    let today = local_now.date_naive();
+
+    // pull the time of day out, everything that remains must describe the date
+    let mut time = None;
+    let mut date_words = Vec::new();
+    let mut iter = words.iter().peekable();
+    while let Some(word) = iter.next() {
+        if time.is_none() {
+            if let Some(meridiem) = iter.peek().filter(|w| matches!(***w, "am" | "pm")) {
+                if let Some(t) = parse_time(&format!("{word}{meridiem}")) {
+                    time = Some(t);
+                    iter.next();
+                    continue;
+                }
+            }
+            if let Some(t) = parse_time(word) {
+                time = Some(t);
+                continue;
+            }
+        }
+        date_words.push(*word);
+    }
+
+    let time = time?;
+
+    // a bare time refers to its next occurrence
+    let (date, period) = if date_words.is_empty() {
+        (today, Some(Duration::days(1)))
+    } else {
+        parse_date(&date_words, today)?
+    };
+
+    let mut dt = offset.from_local_datetime(&date.and_time(time)).single()?;
+    if let Some(period) = period.filter(|_| dt <= local_now) {
+        dt = dt.checked_add_signed(period)?;
+    }
+
+    Some(dt.with_timezone(&Utc))
+}
+
+fn parse_time(word: &str) -> Option<NaiveTime> {
+    match word {
+        "noon" => return NaiveTime::from_hms_opt(12, 0, 0),
+        "midnight" => return NaiveTime::from_hms_opt(0, 0, 0),
+        _ => (),
+    }
+
+    let (clock, pm) = if let Some(clock) = word.strip_suffix("am") {
+        (clock, Some(false))
+    } else if let Some(clock) = word.strip_suffix("pm") {
+        (clock, Some(true))
+    } else {
+        (word, None)
+    };
+
+    let mut parts = clock.split(':');
+    let hour = parts.next()?.parse::<u32>().ok()?;
+    let minute = parts.next().map(str::parse::<u32>);
+    let second = parts.next().map(str::parse::<u32>);
+    if parts.next().is_some() {
+        return None;
+    }
+
+    let hour = match pm {
+        Some(_) if !(1..=12).contains(&hour) => return None,
+        Some(true) => hour % 12 + 12,
+        Some(false) => hour % 12,
+        // a bare number could just as well be a day of the month
+        None if minute.is_none() => return None,
+        None => hour,
+    };
+
+    NaiveTime::from_hms_opt(
+        hour,
+        minute.transpose().ok()?.unwrap_or(0),
+        second.transpose().ok()?.unwrap_or(0),
+    )
+}
+
+/// Parses the date part of an absolute time, along with how far to move it ahead should the
+/// time have already passed that day, for dates that recur.
+fn parse_date(words: &[&str], today: NaiveDate) -> Option<(NaiveDate, Option<Duration>)> {
+    match words {
+        ["today" | "tonight"] => return Some((today, None)),
+        ["tomorrow" | "tmrw" | "tmr"] => return Some((today.succ_opt()?, None)),
+        [date] => {
+            if let Ok(date) = NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+                return Some((date, None));
+            }
+        },
+        _ => (),
+    }
+
+    let (next, weekday) = match words {
+        ["next", day] => (true, day.parse::<Weekday>().ok()),
+        [day] => (false, day.parse::<Weekday>().ok()),
+        _ => (false, None),
+    };
+    if let Some(weekday) = weekday {
+        let mut days_ahead = (7 + weekday.num_days_from_monday() as i64
+            - today.weekday().num_days_from_monday() as i64)
+            % 7;
+        if next && days_ahead == 0 {
+            days_ahead = 7;
+        }
+        // a plain weekday that already passed today means the same day next week
+        let period = Some(Duration::weeks(1)).filter(|_| !next);
+        return Some((
+            today.checked_add_signed(Duration::days(days_ahead))?,
+            period,
+        ));
+    }
+
+    // month names in either order with an optional year, e.g. `oct 20`, `20th october 2026`
+    let mut month = None;
+    let mut day = None;
+    let mut year = None;
+    for word in words {
+        if let Ok(m) = word.trim_end_matches(['.', ',']).parse::<Month>() {
+            month = Some(m);
+        } else if let Ok(n) = word.trim_end_matches(',').parse::<u32>() {
+            if word.trim_end_matches(',').len() == 4 {
+                year = Some(n as i32);
+            } else {
+                day = Some(n);
+            }
+        } else {
+            let d = word
+                .trim_end_matches(',')
+                .trim_end_matches(|c: char| c.is_alphabetic());
+            day = Some(d.parse().ok()?);
+        }
+    }
+
+    let (month, day) = (month?.number_from_month(), day?);
+    let date = match year {
+        Some(year) => NaiveDate::from_ymd_opt(year, month, day)?,
+        None => {
+            let date = NaiveDate::from_ymd_opt(today.year(), month, day)?;
+            if date < today {
+                NaiveDate::from_ymd_opt(today.year() + 1, month, day)?
+            } else {
+                date
+            }
+        },
+    };
+
+    Some((date, None))
+}
+
+/// Parses `utc`, `gmt`, `z` and offsets such as `+2`, `utc+5:30` or `-0400`.
+fn parse_offset(word: &str) -> Option<FixedOffset> {
+    if matches!(word, "utc" | "gmt" | "z") {
+        return FixedOffset::east_opt(0);
+    }
+
+    let rest = word
+        .strip_prefix("utc")
+        .or_else(|| word.strip_prefix("gmt"))
+        .unwrap_or(word);
+
+    let (sign, rest) = if let Some(rest) = rest.strip_prefix('+') {
+        (1, rest)
+    } else if let Some(rest) = rest.strip_prefix('-') {
+        (-1, rest)
+    } else {
+        return None;
+    };
+
+    if rest.is_empty() || !rest.chars().all(|c| c.is_ascii_digit() || c == ':') {
+        return None;
+    }
+
+    let (hours, minutes) = match rest.split_once(':') {
+        Some((h, m)) => (h, m),
+        None if rest.len() == 4 => rest.split_at(2),
+        None => (rest, "0"),
+    };
+
+    let hours = hours.parse::<i32>().ok()?;
+    let minutes = minutes.parse::<i32>().ok()?;
+    if hours > 14 || minutes >= 60 {
+        return None;
+    }
+
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sunday 2026-10-18 12:00:00 UTC.
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 10, 18, 12, 0, 0).unwrap()
+    }
+
+    fn utc(y: i32, mo: u32, d: u32, h: u32, mi: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, mo, d, h, mi, 0).unwrap()
+    }
+
+    fn parse(input: &str) -> DateTime<Utc> {
+        parse_datetime(input, now()).unwrap_or_else(|e| panic!("`{input}`: {e}"))
+    }
+
+    fn rejects(input: &str) -> String {
+        match parse_datetime(input, now()) {
+            Ok(dt) => panic!("`{input}` was accepted as {dt}"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(parse("2026-10-20T19:00:00+02:00"), utc(2026, 10, 20, 17, 0));
+        assert_eq!(parse("1792357200"), utc(2026, 10, 18, 21, 0));
+        assert_eq!(parse("<t:1792357200:F>"), utc(2026, 10, 18, 21, 0));
+        assert_eq!(parse("<t:1792357200>"), utc(2026, 10, 18, 21, 0));
+    }
+
+    #[test]
+    fn millisecond_timestamps() {
+        assert_eq!(parse("1792357200000"), utc(2026, 10, 18, 21, 0));
+    }
+
+    #[test]
+    fn relative() {
+        assert_eq!(parse("in 2h"), utc(2026, 10, 18, 14, 0));
+        assert_eq!(parse("in 1h30m"), utc(2026, 10, 18, 13, 30));
+        assert_eq!(
+            parse("in 2 hours and 15 minutes"),
+            utc(2026, 10, 18, 14, 15)
+        );
+        assert_eq!(parse("in an hour"), utc(2026, 10, 18, 13, 0));
+        assert_eq!(parse("in 3 days"), utc(2026, 10, 21, 12, 0));
+        assert_eq!(parse("in 1 week"), utc(2026, 10, 25, 12, 0));
+    }
+
+    #[test]
+    fn bare_times() {
+        assert_eq!(parse("8pm"), utc(2026, 10, 18, 20, 0));
+        assert_eq!(parse("8 pm"), utc(2026, 10, 18, 20, 0));
+        assert_eq!(parse("19:30"), utc(2026, 10, 18, 19, 30));
+        assert_eq!(parse("noon"), utc(2026, 10, 19, 12, 0));
+        assert_eq!(parse("midnight"), utc(2026, 10, 19, 0, 0));
+        // times that already passed today mean tomorrow
+        assert_eq!(parse("8:00"), utc(2026, 10, 19, 8, 0));
+        assert_eq!(parse("12:00"), utc(2026, 10, 19, 12, 0));
+    }
+
+    #[test]
+    fn days() {
+        assert_eq!(parse("tomorrow 8pm"), utc(2026, 10, 19, 20, 0));
+        assert_eq!(parse("tomorrow at 8pm"), utc(2026, 10, 19, 20, 0));
+        assert_eq!(parse("today 6pm"), utc(2026, 10, 18, 18, 0));
+        assert_eq!(parse("tonight 9:15pm"), utc(2026, 10, 18, 21, 15));
+    }
+
+    #[test]
+    fn weekdays() {
+        assert_eq!(parse("friday 19:30"), utc(2026, 10, 23, 19, 30));
+        assert_eq!(parse("sunday 7pm"), utc(2026, 10, 18, 19, 0));
+        // the same weekday rolls over to next week once the time passed
+        assert_eq!(parse("sunday 7am"), utc(2026, 10, 25, 7, 0));
+        assert_eq!(parse("next sunday 7pm"), utc(2026, 10, 25, 19, 0));
+        assert_eq!(parse("next friday 19:30"), utc(2026, 10, 23, 19, 30));
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(parse("2026-10-20 19:00 UTC"), utc(2026, 10, 20, 19, 0));
+        assert_eq!(parse("oct 20 8:30 pm"), utc(2026, 10, 20, 20, 30));
+        assert_eq!(parse("20th october 2026 8pm"), utc(2026, 10, 20, 20, 0));
+        assert_eq!(parse("on oct 20, 8pm"), utc(2026, 10, 20, 20, 0));
+        // dates that already passed this year mean next year
+        assert_eq!(parse("jan 5 8pm"), utc(2027, 1, 5, 20, 0));
+    }
+
+    #[test]
+    fn offsets() {
+        assert_eq!(parse("friday 19:30 UTC+2"), utc(2026, 10, 23, 17, 30));
+        assert_eq!(parse("tomorrow 8pm +2"), utc(2026, 10, 19, 18, 0));
+        assert_eq!(parse("tomorrow 8pm utc+5:30"), utc(2026, 10, 19, 14, 30));
+        assert_eq!(parse("tomorrow 8pm -0400"), utc(2026, 10, 20, 0, 0));
+        assert_eq!(parse("tomorrow 8pm gmt"), utc(2026, 10, 19, 20, 0));
+        assert_eq!(parse("tomorrow 8pm z"), utc(2026, 10, 19, 20, 0));
+        // the day is taken from the given timezone, where it is already monday
+        assert_eq!(parse("today 11pm utc+13"), utc(2026, 10, 19, 10, 0));
+    }
+
+    #[test]
+    fn past() {
+        assert!(rejects("today 8am").contains("in the past"));
+        assert!(rejects("2026-10-17 19:00").contains("in the past"));
+        assert!(rejects("1700000000").contains("in the past"));
+        assert!(rejects("in 0m").contains("in the past"));
+    }
+
+    #[test]
+    fn too_far_ahead() {
+        assert!(rejects("in 100 weeks").contains("too far"));
+        assert!(rejects("2030-01-01 19:00").contains("too far"));
+        assert!(rejects("17600000000").contains("too far"));
+    }
+
+    #[test]
+    fn invalid() {
+        for input in [
+            "",
+            "soon",
+            "tomorrow",
+            "20",
+            "in",
+            "in 2 fortnights",
+            "13pm",
+            "25:00",
+            "friday 8pm utc+15",
+            "feb 30 8pm",
+            "12345",
+            "176000000000000",
+        ] {
+            assert!(rejects(input).contains("Unable to understand"), "{input}");
+        }
+    }
+}
@@ -1,7 +1,56 @@
-use poise::serenity_prelude::{self as serenity, Context, Reaction};
+use poise::serenity_prelude::{
+    self as serenity,
+    Context,
+    InteractionResponseType,
+    MessageComponentInteraction,
+    Reaction,
+};
 use tracing::{error, info};
 
-use crate::{Data, Error, EMBED_COLOUR, REACT_STR};
+use crate::channels::{forget_channel, revoke_channel_access};
+use crate::history::SignupEventKind;
+use crate::roles::refresh_participant_role;
+use crate::room::{
+    get_roster,
+    notify_roster_changes,
+    orphan_room,
+    register_player,
+    roster_embed,
+    update_room_embed,
+    withdraw_player,
+    Registration,
+    RoomStatus,
+    SignupMode,
+    JOIN_BUTTON_ID,
+    LEAVE_BUTTON_ID,
+    ROSTER_BUTTON_ID,
+};
+use crate::utils::{get_room_capacity, get_room_limit, notify_user};
+use crate::{Data, Error};
+
+/// The result of a player asking to join or leave a room.
+struct SignupOutcome {
+    /// Whether the roster changed, meaning the room's embed is out of date.
+    changed: bool,
+    /// What to tell the player.
+    reply: String,
+}
+
+impl SignupOutcome {
+    fn unchanged(reply: impl Into<String>) -> Self {
+        Self {
+            changed: false,
+            reply: reply.into(),
+        }
+    }
+
+    fn changed(reply: impl Into<String>) -> Self {
+        Self {
+            changed: true,
+            reply: reply.into(),
+        }
+    }
+}
 
 pub async fn handle_on_raw_reaction(
     reaction: &Reaction,
@@ -36,137 +85,301 @@ pub async fn handle_on_raw_reaction(
         return Ok(());
     }
 
+    let mode = sqlx::query!(
+        r#"SELECT signup_mode as "signup_mode: SignupMode" FROM message WHERE message_id = $1"#,
+        message_id.0 as i64
+    )
+    .fetch_one(&data.db_pool)
+    .await?
+    .signup_mode;
+
+    // reactions on rooms using buttons don't mean anything, so we leave them alone
+    if mode != SignupMode::Reactions {
+        return Ok(());
+    }
+
+    let join = if reaction.emoji.unicode_eq("✅") {
+        true
+    } else if reaction.emoji.unicode_eq("❌") {
+        false
+    } else {
+        return Ok(());
+    };
+
     // try to remove the reaction
     channel_id
         .delete_reaction(ctx, message_id, Some(user_id), reaction.emoji.clone())
         .await?;
 
-    if reaction.emoji.unicode_eq("✅") {
-        if !handle_add_user(ctx, data, message_id, user_id).await? {
-            return Ok(());
+    let outcome = if join {
+        handle_add_user(data, guild_id, message_id, user_id).await?
+    } else {
+        handle_remove_user(ctx, data, message_id, user_id).await?
+    };
+
+    if outcome.changed {
+        // update message footer with new number of signups
+        update_room_embed(ctx, &data.db_pool, message_id).await?;
+    }
+
+    // the signup already went through, players with closed dms are told in the channel
+    notify_user(ctx, user_id, channel_id, &outcome.reply).await
+}
+
+/// Processes the ✅ and ❌ reactions players added to reaction rooms while the bot was
+/// offline, since those never reach [`handle_on_raw_reaction`]. Meant to run on every
+/// connect.
+pub async fn reconcile_reactions(
+    ctx: &Context,
+    data: &Data,
+    bot_id: serenity::UserId,
+) -> Result<(), Error> {
+    let rooms = sqlx::query!(
+        "SELECT message_id, guild_id, channel_id FROM message
+        WHERE signup_mode = 'reactions' AND status IN ('open', 'closed')
+        ORDER BY guild_id, num"
+    )
+    .fetch_all(&data.db_pool)
+    .await?;
+
+    for room in rooms {
+        let message_id = serenity::MessageId(room.message_id as u64);
+        if !data.messages.contains(&message_id.0) {
+            continue;
         }
-        info!("registered user {} for room {}", user_id, message_id.0);
-    } else if reaction.emoji.unicode_eq("❌") {
-        if !handle_remove_user(ctx, data, message_id, user_id).await? {
-            return Ok(());
+
+        let guild_id = serenity::GuildId(room.guild_id as u64);
+        let channel_id = serenity::ChannelId(room.channel_id as u64);
+
+        // one broken room shouldn't keep the others from catching up
+        if let Err(e) =
+            reconcile_room_reactions(ctx, data, bot_id, guild_id, channel_id, message_id).await
+        {
+            error!("error reconciling reactions of room {}: {e}", message_id.0);
         }
-        info!("deregistered user {} from room {}", user_id, message_id.0);
-    } else {
-        return Ok(());
     }
 
-    // update message footer with new number of signups
-    let mut msg = if let Some(m) = ctx.cache.message(channel_id, message_id) {
-        m
-    } else {
-        channel_id.message(&ctx, message_id).await?
-    };
+    Ok(())
+}
 
-    if let Some(e) = msg.embeds.first() {
-        let mut embed = serenity::CreateEmbed::default();
+async fn reconcile_room_reactions(
+    ctx: &Context,
+    data: &Data,
+    bot_id: serenity::UserId,
+    guild_id: serenity::GuildId,
+    channel_id: serenity::ChannelId,
+    message_id: serenity::MessageId,
+) -> Result<(), Error> {
+    let joins = get_reaction_users(ctx, channel_id, message_id, '✅', bot_id).await?;
+    let leaves = get_reaction_users(ctx, channel_id, message_id, '❌', bot_id).await?;
 
-        embed.colour(e.colour.unwrap_or_else(|| EMBED_COLOUR.into()));
+    if joins.is_empty() && leaves.is_empty() {
+        return Ok(());
+    }
 
-        if let Some(ref title) = e.title {
-            embed.title(title);
+    info!(
+        "processing {} missed join(s) and {} missed leave(s) for room {}",
+        joins.len(),
+        leaves.len(),
+        message_id.0
+    );
+
+    // discord doesn't tell us when each reaction was added, so we go by user ID. players
+    // who reacted with both cancel out, as we can't know which one they meant last
+    for &user_id in joins.iter().filter(|u| !leaves.contains(u)) {
+        let outcome = handle_add_user(data, guild_id, message_id, user_id).await?;
+        if let Err(e) = notify_user(ctx, user_id, channel_id, &outcome.reply).await {
+            error!(
+                "unable to notify user {} of a missed reaction: {e}",
+                user_id
+            );
         }
+    }
 
-        if let Some(ref desc) = e.description {
-            embed.description(desc);
+    for &user_id in leaves.iter().filter(|u| !joins.contains(u)) {
+        let outcome = handle_remove_user(ctx, data, message_id, user_id).await?;
+        if let Err(e) = notify_user(ctx, user_id, channel_id, &outcome.reply).await {
+            error!(
+                "unable to notify user {} of a missed reaction: {e}",
+                user_id
+            );
         }
+    }
 
-        let record = sqlx::query!(
-            "SELECT COUNT(*) FROM signup WHERE message_id = $1",
-            message_id.0 as i64
-        )
-        .fetch_one(&data.db_pool)
+    // clearing both emojis takes far fewer requests than removing every reaction, so we put
+    // back our own reactions afterwards. only open rooms have them, as in `transition`
+    channel_id
+        .delete_reaction_emoji(ctx, message_id, '✅')
+        .await?;
+    channel_id
+        .delete_reaction_emoji(ctx, message_id, '❌')
         .await?;
 
-        embed.footer(|f| {
-            f.text(format!(
-                "{}/15 spots available | {REACT_STR}",
-                15 - record.count.unwrap_or(0)
-            ))
-        });
-        msg.edit(&ctx, |m| m.set_embed(embed)).await?;
+    if get_room_status(message_id, data).await? == RoomStatus::Open {
+        channel_id.create_reaction(ctx, message_id, '✅').await?;
+        channel_id.create_reaction(ctx, message_id, '❌').await?;
     }
 
-    Ok(())
+    update_room_embed(ctx, &data.db_pool, message_id).await
 }
 
-async fn handle_add_user(
+/// Returns everyone but the bot who reacted to a message with `emoji`, sorted by ID.
+async fn get_reaction_users(
     ctx: &Context,
-    data: &Data,
+    channel_id: serenity::ChannelId,
     message_id: serenity::MessageId,
-    user_id: serenity::UserId,
-) -> Result<bool, Error> {
-    if let Some((mid, num)) = check_if_registered_any(user_id, data).await? {
-        if message_id == mid as u64 {
-            dm_user(ctx, user_id, "You are already registered for this room.").await?;
-        } else {
-            dm_user(
-                ctx,
-                user_id,
-                format!(
-                    "You can only register for one room. \
-                    You are currently registered for room #{}.",
-                    num
-                ),
-            )
+    emoji: char,
+    bot_id: serenity::UserId,
+) -> Result<Vec<serenity::UserId>, Error> {
+    let mut users = Vec::new();
+    let mut after = None;
+
+    loop {
+        let page = channel_id
+            .reaction_users(ctx, message_id, emoji, Some(100), after)
             .await?;
+
+        let done = page.len() < 100;
+        after = page.last().map(|u| u.id);
+        users.extend(page.into_iter().map(|u| u.id).filter(|&u| u != bot_id));
+
+        if done {
+            break;
         }
-        return Ok(false);
     }
 
-    let record = sqlx::query!(
-        "SELECT COUNT(*) FROM signup WHERE message_id = $1",
-        message_id.0 as i64
-    )
-    .fetch_one(&data.db_pool)
-    .await?;
+    users.sort_unstable();
+    Ok(users)
+}
 
-    let count = record.count.unwrap_or_default();
-    if count >= 15 {
-        return Ok(false);
+/// Handles the join, leave and roster buttons of rooms, answering the player privately.
+pub async fn handle_component(
+    component: &MessageComponentInteraction,
+    ctx: &Context,
+    data: &Data,
+) -> Result<(), Error> {
+    let guild_id = match component.guild_id {
+        Some(gid) => gid,
+        None => return Ok(()),
+    };
+
+    let message_id = component.message.id;
+    let custom_id = component.data.custom_id.as_str();
+
+    if !data.messages.contains(&message_id.0)
+        || ![JOIN_BUTTON_ID, LEAVE_BUTTON_ID, ROSTER_BUTTON_ID].contains(&custom_id)
+    {
+        return Ok(());
+    }
+
+    // signing up can take a while when reserves have to be notified
+    component
+        .create_interaction_response(ctx, |r| {
+            r.kind(InteractionResponseType::DeferredChannelMessageWithSource)
+                .interaction_response_data(|d| d.ephemeral(true))
+        })
+        .await?;
+
+    let user_id = component.user.id;
+
+    if custom_id == ROSTER_BUTTON_ID {
+        let embed = roster_embed(&data.db_pool, message_id, None, false).await?;
+        component
+            .edit_original_interaction_response(ctx, |r| r.set_embed(embed))
+            .await?;
+        return Ok(());
     }
 
-    let mut dm = match dm_user(ctx, user_id, "Registering...").await {
-        Ok(m) => m,
-        Err(_) => return Ok(false),
+    let outcome = if custom_id == JOIN_BUTTON_ID {
+        handle_add_user(data, guild_id, message_id, user_id).await?
+    } else {
+        handle_remove_user(ctx, data, message_id, user_id).await?
     };
 
-    if let Err(e) = sqlx::query!(
-        "INSERT INTO signup (message_id, user_id) VALUES ($1, $2)
-                ON CONFLICT (message_id, user_id) DO NOTHING",
-        message_id.0 as i64,
-        user_id.0 as i64,
+    component
+        .edit_original_interaction_response(ctx, |r| r.content(&outcome.reply))
+        .await?;
+
+    if outcome.changed {
+        update_room_embed(ctx, &data.db_pool, message_id).await?;
+    }
+
+    Ok(())
+}
+
+async fn handle_add_user(
+    data: &Data,
+    guild_id: serenity::GuildId,
+    message_id: serenity::MessageId,
+    user_id: serenity::UserId,
+) -> Result<SignupOutcome, Error> {
+    let limit = get_room_limit(data, guild_id);
+
+    let (position, capacity) = match register_player(
+        &data.db_pool,
+        guild_id,
+        message_id,
+        user_id,
+        limit,
+        false,
+        user_id,
     )
-    .execute(&data.db_pool)
     .await
     {
-        dm.edit(&ctx, |f| {
-            f.content(format!(
-                "There was an error registering. Please contact \
-                an DBC Sheriff with the following error:\n\n```{e}```"
+        Ok(Registration::Registered { position, capacity }) => (position, capacity),
+        Ok(Registration::Closed) => {
+            return Ok(SignupOutcome::unchanged(
+                "Registrations for this room are closed.",
             ))
-        })
-        .await?;
-        error!("error registering user: {e}");
+        },
+        Ok(Registration::AlreadyRegistered) => {
+            return Ok(SignupOutcome::unchanged(
+                "You are already registered for this room.",
+            ))
+        },
+        Ok(Registration::LimitReached(active_rooms)) => {
+            let rooms = active_rooms
+                .iter()
+                .map(|num| format!("#{num}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            let reply = if limit == 1 {
+                format!(
+                    "You can only register for one room. \
+                        You are currently registered for room {rooms}."
+                )
+            } else {
+                format!(
+                    "You can only register for {limit} rooms at a time. \
+                        You are currently registered for rooms {rooms}."
+                )
+            };
+            return Ok(SignupOutcome::unchanged(reply));
+        },
+        Ok(Registration::Full) => return Ok(SignupOutcome::unchanged("This room is full.")),
+        Err(e) => {
+            error!("error registering user: {e}");
+            return Ok(SignupOutcome::unchanged(format!(
+                "There was an error registering. Please contact \
+                    an DBC Sheriff with the following error:\n\n```{e}```"
+            )));
+        },
     };
 
-    dm.edit(ctx, |m| {
-        if count < 9 {
-            m.content("You registered for the room.")
-        } else {
-            m.content(format!(
-                "You registered as a reserve for the room. Your position is {}/6.",
-                count - 9 + 1
-            ))
-        }
-    })
-    .await?;
+    info!("registered user {} for room {}", user_id, message_id.0);
+
+    let main = capacity.main as i64;
 
-    Ok(true)
+    if position < main {
+        Ok(SignupOutcome::changed("You registered for the room."))
+    } else {
+        Ok(SignupOutcome::changed(format!(
+            "You registered as a reserve for the room. Your position is {}/{}.",
+            position - main + 1,
+            capacity.reserve
+        )))
+    }
 }
 
 async fn handle_remove_user(
@@ -174,38 +387,73 @@ async fn handle_remove_user(
     data: &Data,
     message_id: serenity::MessageId,
     user_id: serenity::UserId,
-) -> Result<bool, Error> {
-    if !check_if_registered(message_id, user_id, data).await? {
-        return Ok(false);
+) -> Result<SignupOutcome, Error> {
+    let status = get_room_status(message_id, data).await?;
+    if !status.accepts_withdrawals() {
+        return Ok(SignupOutcome::unchanged(
+            "You can't deregister from this room anymore.",
+        ));
     }
 
-    let mut dm = match dm_user(ctx, user_id, "Deregistering...").await {
-        Ok(m) => m,
-        Err(_) => return Ok(false),
-    };
+    if !check_if_registered(message_id, user_id, data).await? {
+        return Ok(SignupOutcome::unchanged(
+            "You are not registered for this room.",
+        ));
+    }
 
-    if let Err(e) = sqlx::query!(
-        "DELETE FROM signup WHERE message_id = $1 AND user_id = $2",
-        message_id.0 as i64,
-        user_id.0 as i64,
+    let roster = get_roster(&data.db_pool, message_id).await?;
+    let capacity = get_room_capacity(message_id, &data.db_pool).await?;
+
+    match withdraw_player(
+        &data.db_pool,
+        message_id,
+        user_id,
+        SignupEventKind::Leave,
+        Some(user_id),
+        None,
+        false,
     )
-    .execute(&data.db_pool)
     .await
     {
-        dm.edit(&ctx, |f| {
-            f.content(format!(
+        Ok(true) => (),
+        // they deregistered twice in quick succession
+        Ok(false) => {
+            return Ok(SignupOutcome::unchanged(
+                "You are not registered for this room.",
+            ))
+        },
+        Err(e) => {
+            error!("error deregistering user: {e}");
+            return Ok(SignupOutcome::unchanged(format!(
                 "There was an error deregistering. Please contact \
                 an FNF Host with the following error:\n\n```{e}```"
-            ))
-        })
-        .await?;
-        error!("error deregistering user: {e}");
-    };
+            )));
+        },
+    }
 
-    dm.edit(ctx, |m| m.content("You have deregistered from the room."))
-        .await?;
+    info!("deregistered user {} from room {}", user_id, message_id.0);
+
+    revoke_channel_access(ctx, &data.db_pool, message_id, user_id).await?;
+    refresh_participant_role(ctx, &data.db_pool, message_id).await;
 
-    Ok(true)
+    notify_roster_changes(ctx, data, message_id, &roster, capacity.main, None).await?;
+
+    Ok(SignupOutcome::changed(
+        "You have deregistered from the room.",
+    ))
+}
+
+async fn get_room_status(
+    message_id: serenity::MessageId,
+    data: &Data,
+) -> Result<RoomStatus, Error> {
+    let record = sqlx::query!(
+        r#"SELECT status as "status: RoomStatus" FROM message WHERE message_id = $1"#,
+        message_id.0 as i64
+    )
+    .fetch_one(&data.db_pool)
+    .await?;
+    Ok(record.status)
 }
 
 async fn check_if_registered(
@@ -223,38 +471,122 @@ async fn check_if_registered(
     Ok(record.exists)
 }
 
-async fn check_if_registered_any(
-    user_id: serenity::UserId,
+/// Cancels rooms whose messages were deleted.
+pub async fn handle_messages_deleted(
+    ctx: &Context,
     data: &Data,
-) -> Result<Option<(i64, i32)>, Error> {
-    let record = sqlx::query!(
-        "SELECT m.message_id, num FROM signup s JOIN message m ON user_id = $1 AND s.message_id = m.message_id;",
-        user_id.0 as i64,
+    message_ids: &[serenity::MessageId],
+) -> Result<(), Error> {
+    for &message_id in message_ids {
+        orphan_room(ctx, data, message_id, "the room's message was deleted.").await?;
+    }
+
+    Ok(())
+}
+
+/// Cancels the rooms in a deleted channel and unsets it as the fnf channel if it was one.
+pub async fn handle_channel_delete(
+    ctx: &Context,
+    data: &Data,
+    channel: &serenity::GuildChannel,
+) -> Result<(), Error> {
+    let rooms = sqlx::query!(
+        "SELECT message_id FROM message WHERE channel_id = $1",
+        channel.id.0 as i64
     )
-    .fetch_optional(&data.db_pool)
+    .fetch_all(&data.db_pool)
     .await?;
-    Ok(record.map(|r| (r.message_id, r.num)))
+
+    for room in rooms {
+        let message_id = serenity::MessageId(room.message_id as u64);
+        orphan_room(ctx, data, message_id, "the room's channel was deleted.").await?;
+    }
+
+    // a private room channel deleted by hand simply stops being managed
+    forget_channel(&data.db_pool, channel.id).await?;
+
+    let was_fnf_channel = match data.guild_configs.get_mut(&channel.guild_id.0) {
+        Some(mut config) if config.channel_id == Some(channel.id.0) => {
+            config.channel_id = None;
+            true
+        },
+        _ => false,
+    };
+
+    if was_fnf_channel {
+        sqlx::query!(
+            "UPDATE config SET fnf_channel_id = NULL WHERE guild_id = $1",
+            channel.guild_id.0 as i64
+        )
+        .execute(&data.db_pool)
+        .await?;
+
+        info!("fnf channel of guild {} was deleted", channel.guild_id);
+    }
+
+    Ok(())
 }
 
-async fn dm_user(
+/// Drops a player who left the guild from the rooms they can still withdraw from, so they
+/// don't hold a slot, and notes their departure for the hosts.
+pub async fn handle_member_removal(
     ctx: &Context,
+    data: &Data,
+    guild_id: serenity::GuildId,
     user_id: serenity::UserId,
-    content: impl std::fmt::Display,
-) -> Result<serenity::Message, Error> {
-    match user_id.create_dm_channel(&ctx).await {
-        Ok(c) => match c.say(&ctx, content).await {
-            Ok(m) => Ok(m),
-            Err(e) => {
-                error!("unable to dm user {}, error: {}", user_id, e);
-                Err(e.into())
-            },
-        },
-        Err(e) => {
-            error!(
-                "unable to create dm channel with user {}, error: {}",
-                user_id, e
-            );
-            Err(e.into())
-        },
+) -> Result<(), Error> {
+    let rooms = sqlx::query!(
+        "SELECT s.message_id FROM signup s JOIN message m ON s.message_id = m.message_id
+        WHERE m.guild_id = $1 AND s.user_id = $2 AND m.status IN ('open', 'closed')",
+        guild_id.0 as i64,
+        user_id.0 as i64,
+    )
+    .fetch_all(&data.db_pool)
+    .await?;
+
+    // discord takes their roles away already
+    sqlx::query!(
+        "DELETE FROM role_grant r USING message m
+        WHERE r.message_id = m.message_id AND m.guild_id = $1 AND r.user_id = $2",
+        guild_id.0 as i64,
+        user_id.0 as i64,
+    )
+    .execute(&data.db_pool)
+    .await?;
+
+    for room in rooms {
+        let message_id = serenity::MessageId(room.message_id as u64);
+
+        let roster = get_roster(&data.db_pool, message_id).await?;
+        let capacity = get_room_capacity(message_id, &data.db_pool).await?;
+
+        let withdrawn = withdraw_player(
+            &data.db_pool,
+            message_id,
+            user_id,
+            SignupEventKind::Leave,
+            None,
+            Some("left the server"),
+            true,
+        )
+        .await?;
+
+        // a signup that went away since the rooms were looked up needs no cleanup
+        if !withdrawn {
+            continue;
+        }
+
+        info!(
+            "deregistered user {} from room {} after they left the guild",
+            user_id, message_id.0
+        );
+
+        // their overwrites outlive their membership, and would apply again if they rejoin
+        revoke_channel_access(ctx, &data.db_pool, message_id, user_id).await?;
+        refresh_participant_role(ctx, &data.db_pool, message_id).await;
+        notify_roster_changes(ctx, data, message_id, &roster, capacity.main, None).await?;
+        update_room_embed(ctx, &data.db_pool, message_id).await?;
     }
+
+    Ok(())
 }
@@ -0,0 +1,149 @@
+use chrono::{DateTime, Utc};
+use poise::serenity_prelude::{GuildId, Mentionable, MessageId, UserId};
+use sqlx::{Executor, PgPool, Postgres};
+
+use crate::utils::get_message_id;
+use crate::Error;
+
+/// A change to a room's roster.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "signup_event_kind", rename_all = "lowercase")]
+pub enum SignupEventKind {
+    Join,
+    Leave,
+    /// The player moved from the reserves to the main roster.
+    Promote,
+    /// The player moved from the main roster to the reserves.
+    Demote,
+    Kick,
+    /// A host moved the player into or out of the room.
+    Moved,
+}
+
+/// Appends an event to a room's history, which is kept even after the room is removed.
+/// `actor` is whoever caused it, `None` meaning the bot itself.
+pub async fn record_event<'c, E>(
+    executor: E,
+    message_id: MessageId,
+    user_id: UserId,
+    kind: SignupEventKind,
+    actor: Option<UserId>,
+    detail: Option<&str>,
+) -> Result<(), Error>
+where
+    E: Executor<'c, Database = Postgres>,
+{
+    let inserted = sqlx::query!(
+        "INSERT INTO signup_event (message_id, guild_id, num, user_id, kind, actor_id, detail)
+        SELECT message_id, guild_id, num, $2, $3, $4, $5 FROM message WHERE message_id = $1",
+        message_id.0 as i64,
+        user_id.0 as i64,
+        kind as SignupEventKind,
+        actor.map(|a| a.0 as i64),
+        detail,
+    )
+    .execute(executor)
+    .await?
+    .rows_affected();
+
+    if inserted == 0 {
+        return Err(format!("room {} not found", message_id.0).into());
+    }
+
+    Ok(())
+}
+
+/// Finds the room whose history to show, returning its message ID and number. Rooms that
+/// still exist are looked up like any other room, removed ones by their number or message
+/// ID. If several removed rooms had the number, the latest one is picked.
+pub async fn find_history_room(
+    pool: &PgPool,
+    input: &str,
+    guild_id: GuildId,
+) -> Result<(MessageId, i32), Error> {
+    if let Ok(message_id) = get_message_id(input, guild_id, pool).await {
+        let num = sqlx::query!(
+            "SELECT num FROM message WHERE message_id = $1",
+            message_id.0 as i64
+        )
+        .fetch_one(pool)
+        .await?
+        .num;
+
+        return Ok((message_id, num));
+    }
+
+    let record = sqlx::query!(
+        "SELECT message_id, num FROM signup_event
+        WHERE guild_id = $1 AND (num = $2 OR message_id = $3)
+        ORDER BY event_id DESC LIMIT 1",
+        guild_id.0 as i64,
+        input.parse::<i32>().ok(),
+        input.parse::<i64>().ok(),
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    match record {
+        Some(r) => Ok((MessageId(r.message_id as u64), r.num)),
+        None => Err("unable to find room with given number or message ID".into()),
+    }
+}
+
+/// Returns a room's history as one line per event, oldest first.
+pub async fn get_timeline(pool: &PgPool, message_id: MessageId) -> Result<Vec<String>, Error> {
+    let events = sqlx::query!(
+        r#"SELECT event_id, user_id, kind as "kind: SignupEventKind", actor_id, detail, created_at
+        FROM signup_event WHERE message_id = $1 ORDER BY event_id"#,
+        message_id.0 as i64
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(events
+        .into_iter()
+        .map(|e| {
+            let user = UserId(e.user_id as u64);
+            let actor = e.actor_id.map(|a| UserId(a as u64));
+            format_event(e.event_id, e.created_at, user, e.kind, actor, e.detail)
+        })
+        .collect())
+}
+
+fn format_event(
+    event_id: i64,
+    created_at: DateTime<Utc>,
+    user: UserId,
+    kind: SignupEventKind,
+    actor: Option<UserId>,
+    detail: Option<String>,
+) -> String {
+    let action = match kind {
+        SignupEventKind::Join => "joined",
+        SignupEventKind::Leave => "left",
+        SignupEventKind::Promote => "was promoted to the main roster",
+        SignupEventKind::Demote => "was moved to the reserves",
+        SignupEventKind::Kick => "was kicked",
+        SignupEventKind::Moved => "was moved",
+    };
+
+    // seconds are shown too, as the order of signups close together is what gets disputed.
+    // the event ID settles ties
+    let ts = created_at.timestamp();
+    let mut line = format!(
+        "`#{event_id}` <t:{ts}:d> <t:{ts}:T> {} {action}",
+        user.mention()
+    );
+
+    if let Some(detail) = detail {
+        line.push_str(&format!(" ({detail})"));
+    }
+
+    match actor {
+        Some(actor) if actor != user => line.push_str(&format!(" by {}", actor.mention())),
+        Some(_) => (),
+        None => line.push_str(" automatically"),
+    }
+
+    line
+}
@@ -0,0 +1,347 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use poise::serenity_prelude::{self as serenity, GuildId, MessageId};
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use sqlx::{PgExecutor, PgPool};
+use tracing::{error, info, warn};
+
+use crate::room::{
+    transition,
+    RoomStatus,
+    DEFAULT_CLOSE_LEAD_MINUTES,
+    DEFAULT_ROOM_DURATION_MINUTES,
+};
+use crate::{reminders, Error};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(15);
+/// How long a claimed job may run before another worker assumes it crashed and retries it.
+const LEASE: Duration = Duration::from_secs(5 * 60);
+const MAX_ATTEMPTS: i32 = 5;
+const PURGE_AFTER_DAYS: i64 = 7;
+
+/// An action to run at a later point in time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Job {
+    /// Reminds a room's players that it starts soon.
+    Reminder {
+        message_id: u64,
+        minutes_before: i32,
+    },
+    /// Stops accepting signups for a room that is still open.
+    CloseRegistration { message_id: u64 },
+    /// Marks a room as finished, regardless of whether it was marked as started.
+    FinishRoom { message_id: u64 },
+    /// Deletes old finished jobs and schedules the next purge.
+    PurgeJobs,
+}
+
+impl Job {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Reminder { .. } => "reminder",
+            Self::CloseRegistration { .. } => "close_registration",
+            Self::FinishRoom { .. } => "finish_room",
+            Self::PurgeJobs => "purge_jobs",
+        }
+    }
+
+    /// The room the job belongs to. Jobs of a room are deleted along with it.
+    pub fn message_id(&self) -> Option<MessageId> {
+        match self {
+            Self::Reminder { message_id, .. }
+            | Self::CloseRegistration { message_id }
+            | Self::FinishRoom { message_id } => Some(MessageId(*message_id)),
+            Self::PurgeJobs => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "job_status", rename_all = "lowercase")]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Done,
+    Failed,
+}
+
+/// Schedules `job` to run at `run_at`.
+pub async fn schedule<'e>(
+    executor: impl PgExecutor<'e>,
+    job: Job,
+    run_at: DateTime<Utc>,
+) -> Result<(), Error> {
+    sqlx::query!(
+        "INSERT INTO job (kind, message_id, payload, run_at) VALUES ($1, $2, $3, $4)",
+        job.kind(),
+        job.message_id().map(|m| m.0 as i64),
+        Json(&job) as _,
+        run_at,
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Deletes jobs of the given kind for a room that haven't started running yet.
+pub async fn unschedule<'e>(
+    executor: impl PgExecutor<'e>,
+    message_id: MessageId,
+    kind: &str,
+) -> Result<(), Error> {
+    sqlx::query!(
+        "DELETE FROM job WHERE message_id = $1 AND kind = $2 AND status = 'pending'",
+        message_id.0 as i64,
+        kind,
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Replaces the pending reminders, registration closing and finishing jobs of a room with
+/// ones based on its start time and the guild's configuration.
+pub async fn schedule_room_jobs(
+    pool: &PgPool,
+    guild_id: GuildId,
+    message_id: MessageId,
+    starts_at: DateTime<Utc>,
+) -> Result<(), Error> {
+    reminders::schedule_reminders(pool, guild_id, message_id, starts_at).await?;
+
+    let config = sqlx::query!(
+        "SELECT close_lead_minutes, room_duration_minutes FROM config WHERE guild_id = $1",
+        guild_id.0 as i64
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    let lead = config
+        .as_ref()
+        .and_then(|c| c.close_lead_minutes)
+        .unwrap_or(DEFAULT_CLOSE_LEAD_MINUTES);
+    let duration = config
+        .as_ref()
+        .and_then(|c| c.room_duration_minutes)
+        .unwrap_or(DEFAULT_ROOM_DURATION_MINUTES);
+
+    let mut tx = pool.begin().await?;
+
+    unschedule(&mut tx, message_id, "close_registration").await?;
+    unschedule(&mut tx, message_id, "finish_room").await?;
+
+    let close_at = starts_at - chrono::Duration::minutes(lead as i64);
+    let job = Job::CloseRegistration {
+        message_id: message_id.0,
+    };
+    schedule(&mut tx, job, close_at.max(Utc::now())).await?;
+
+    if duration > 0 {
+        let job = Job::FinishRoom {
+            message_id: message_id.0,
+        };
+        schedule(
+            &mut tx,
+            job,
+            starts_at + chrono::Duration::minutes(duration as i64),
+        )
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Deletes every job of a room that hasn't started running yet.
+pub async fn unschedule_all<'e>(
+    executor: impl PgExecutor<'e>,
+    message_id: MessageId,
+) -> Result<(), Error> {
+    sqlx::query!(
+        "DELETE FROM job WHERE message_id = $1 AND status = 'pending'",
+        message_id.0 as i64,
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Runs due jobs until the bot shuts down. Meant to be spawned once at startup.
+pub async fn run(ctx: serenity::Context, pool: PgPool) {
+    if let Err(e) = ensure_purge_scheduled(&pool).await {
+        error!("error scheduling job purge: {e}");
+    }
+
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        // keep going until there is nothing left to do, so a backlog after downtime
+        // doesn't take one poll per job
+        loop {
+            match run_next_job(&ctx, &pool).await {
+                Ok(true) => (),
+                Ok(false) => break,
+                Err(e) => {
+                    error!("error running jobs: {e}");
+                    break;
+                },
+            }
+        }
+    }
+}
+
+/// Claims and runs a single due job. Returns false if no job was due.
+async fn run_next_job(ctx: &serenity::Context, pool: &PgPool) -> Result<bool, Error> {
+    let lease_until = Utc::now() + chrono::Duration::from_std(LEASE)?;
+
+    // jobs whose lease expired were claimed by a worker that died while running them
+    let claimed = sqlx::query!(
+        r#"UPDATE job SET status = 'running', attempts = attempts + 1, locked_until = $1
+        WHERE id = (
+            SELECT id FROM job
+            WHERE (status = 'pending' AND run_at <= now())
+                OR (status = 'running' AND locked_until < now())
+            ORDER BY run_at
+            LIMIT 1
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING id, attempts, payload as "payload: Json<Job>""#,
+        lease_until,
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    let claimed = match claimed {
+        Some(c) => c,
+        None => return Ok(false),
+    };
+
+    let job = claimed.payload.0;
+    let res = match tokio::time::timeout(LEASE, run_job(ctx, pool, claimed.id, &job)).await {
+        Ok(res) => res,
+        Err(_) => Err("job timed out".into()),
+    };
+
+    match res {
+        Ok(()) => {
+            sqlx::query!(
+                "UPDATE job SET status = 'done', completed_at = now(), locked_until = NULL
+                WHERE id = $1",
+                claimed.id
+            )
+            .execute(pool)
+            .await?;
+
+            info!("ran {} job {}", job.kind(), claimed.id);
+        },
+        Err(e) => {
+            let status = if claimed.attempts >= MAX_ATTEMPTS {
+                error!("{} job {} failed for good: {e}", job.kind(), claimed.id);
+                JobStatus::Failed
+            } else {
+                warn!("{} job {} failed, retrying: {e}", job.kind(), claimed.id);
+                JobStatus::Pending
+            };
+
+            sqlx::query!(
+                "UPDATE job SET status = $2, run_at = $3, locked_until = NULL, last_error = $4,
+                completed_at = CASE WHEN $2 = 'failed'::job_status THEN now() END
+                WHERE id = $1",
+                claimed.id,
+                status as JobStatus,
+                Utc::now() + backoff(claimed.attempts),
+                e.to_string(),
+            )
+            .execute(pool)
+            .await?;
+        },
+    }
+
+    Ok(true)
+}
+
+async fn run_job(
+    ctx: &serenity::Context,
+    pool: &PgPool,
+    job_id: i64,
+    job: &Job,
+) -> Result<(), Error> {
+    match *job {
+        Job::Reminder { message_id, .. } => {
+            reminders::send_reminder(ctx, pool, job_id, MessageId(message_id)).await
+        },
+        Job::CloseRegistration { message_id } => {
+            let from = [RoomStatus::Open];
+            transition(ctx, pool, MessageId(message_id), &from, RoomStatus::Closed).await?;
+            Ok(())
+        },
+        Job::FinishRoom { message_id } => {
+            let from = [RoomStatus::Open, RoomStatus::Closed, RoomStatus::Started];
+            transition(
+                ctx,
+                pool,
+                MessageId(message_id),
+                &from,
+                RoomStatus::Finished,
+            )
+            .await?;
+            Ok(())
+        },
+        Job::PurgeJobs => {
+            sqlx::query!(
+                "DELETE FROM job WHERE status IN ('done', 'failed')
+                AND completed_at < now() - make_interval(days => $1)",
+                PURGE_AFTER_DAYS as i32
+            )
+            .execute(pool)
+            .await?;
+
+            schedule(pool, Job::PurgeJobs, Utc::now() + chrono::Duration::days(1)).await
+        },
+    }
+}
+
+/// Marks `step` of a job as done, returning false if an earlier attempt already did so.
+pub async fn claim_step(pool: &PgPool, job_id: i64, step: &str) -> Result<bool, Error> {
+    let inserted = sqlx::query!(
+        "INSERT INTO job_step (job_id, step) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+        job_id,
+        step,
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    Ok(inserted == 1)
+}
+
+async fn ensure_purge_scheduled(pool: &PgPool) -> Result<(), Error> {
+    let record = sqlx::query!(
+        r#"SELECT exists (SELECT 1 FROM job WHERE kind = $1 AND status IN ('pending', 'running'))
+        as "exists!""#,
+        Job::PurgeJobs.kind()
+    )
+    .fetch_one(pool)
+    .await?;
+
+    if !record.exists {
+        schedule(pool, Job::PurgeJobs, Utc::now()).await?;
+    }
+
+    Ok(())
+}
+
+/// Waits 30 seconds after the first failure, doubling with every attempt up to an hour.
+fn backoff(attempts: i32) -> chrono::Duration {
+    let secs = 30i64 << (attempts - 1).clamp(0, 7);
+    chrono::Duration::seconds(secs.min(60 * 60))
+}
@@ -1,5 +1,13 @@
+mod channels;
 mod commands;
+mod datetime;
 mod events;
+mod history;
+mod jobs;
+mod panel;
+mod reminders;
+mod roles;
+mod room;
 mod utils;
 
 use std::env;
@@ -12,7 +20,11 @@ use poise::Event;
 use tracing::{error, info, instrument, trace};
 
 pub const REACT_STR: &str = "react to this message to register";
+pub const BUTTON_STR: &str = "press Join to register";
 pub const EMBED_COLOUR: u32 = 0x007FB3;
+pub const DEFAULT_MAIN_SLOTS: i32 = 9;
+pub const DEFAULT_RESERVE_SLOTS: i32 = 6;
+pub const DEFAULT_ROOM_LIMIT: i32 = 1;
 
 // Types used by all command functions
 type Error = Box<dyn std::error::Error + Send + Sync>;
@@ -30,6 +42,12 @@ pub struct Data {
 pub struct GuildConfig {
     channel_id: Option<u64>,
     host_id: Option<u64>,
+    capacity: Option<utils::Capacity>,
+    /// Maximum number of active rooms a player may sign up for, `0` meaning unlimited.
+    room_limit: Option<i32>,
+    signup_mode: Option<room::SignupMode>,
+    /// Channel room control panels are sent to instead of the host's DMs.
+    panel_channel_id: Option<u64>,
 }
 
 /// Show this help menu
@@ -104,15 +122,20 @@ async fn on_error(error: poise::FrameworkError<'_, Data, Error>) {
 }
 
 async fn get_all_messages(pool: &sqlx::PgPool) -> Result<DashSet<u64>, Error> {
-    let res = sqlx::query!("SELECT * FROM message")
+    let res = sqlx::query!("SELECT message_id FROM message")
         .fetch_all(pool)
         .await?;
 
-    Ok(res.iter().map(|r| (r.message_id as u64)).collect())
+    Ok(res.iter().map(|r| r.message_id as u64).collect())
 }
 
 async fn get_guild_configs(pool: &sqlx::PgPool) -> Result<DashMap<u64, GuildConfig>, Error> {
-    let res = sqlx::query!("SELECT * FROM config").fetch_all(pool).await?;
+    let res = sqlx::query!(
+        r#"SELECT guild_id, fnf_channel_id, host_role_id, main_slots, reserve_slots, room_limit,
+        signup_mode as "signup_mode: room::SignupMode", panel_channel_id FROM config"#
+    )
+    .fetch_all(pool)
+    .await?;
 
     Ok(res
         .iter()
@@ -120,6 +143,13 @@ async fn get_guild_configs(pool: &sqlx::PgPool) -> Result<DashMap<u64, GuildConf
             (r.guild_id as u64, GuildConfig {
                 channel_id: r.fnf_channel_id.map(|i| i as u64),
                 host_id: r.host_role_id.map(|i| i as u64),
+                capacity: r.main_slots.map(|main| utils::Capacity {
+                    main,
+                    reserve: r.reserve_slots.unwrap_or(0),
+                }),
+                room_limit: r.room_limit,
+                signup_mode: r.signup_mode,
+                panel_channel_id: r.panel_channel_id.map(|i| i as u64),
             })
         })
         .collect())
@@ -135,6 +165,8 @@ async fn invite_url(
             Permissions::VIEW_CHANNEL
                 | Permissions::SEND_MESSAGES
                 | Permissions::MANAGE_MESSAGES
+                | Permissions::MANAGE_CHANNELS
+                | Permissions::MANAGE_ROLES
                 | Permissions::ADD_REACTIONS
                 | Permissions::EMBED_LINKS
                 | Permissions::ATTACH_FILES
@@ -147,19 +179,48 @@ async fn invite_url(
 
 #[instrument]
 async fn app() -> Result<(), Error> {
+    let commands = vec![
+        help(),
+        register(),
+        commands::host(),
+        commands::editroom(),
+        commands::registrations(),
+        commands::history(),
+        commands::sethost(),
+        commands::setcapacity(),
+        commands::setroomlimit(),
+        commands::signupmode(),
+        commands::setreminders(),
+        commands::reminderping(),
+        commands::setautoclose(),
+        commands::setduration(),
+        commands::shutdown(),
+        commands::remove(),
+        commands::fnfchannel(),
+        commands::panelchannel(),
+        commands::panel(),
+        commands::roomcategory(),
+        commands::roomvoice(),
+        commands::roomcleanup(),
+        commands::participantrole(),
+        commands::removeall(),
+        commands::addplayers(),
+        commands::removeplayers(),
+        commands::addplayer(),
+        commands::kick(),
+        commands::swap(),
+        commands::moveplayer(),
+        commands::kick_menu(),
+        commands::add_menu(),
+        commands::open(),
+        commands::close(),
+        commands::start(),
+        commands::finish(),
+        commands::cancel(),
+    ];
+
     let options = poise::FrameworkOptions {
-        commands: vec![
-            help(),
-            register(),
-            commands::host(),
-            commands::registrations(),
-            commands::sethost(),
-            commands::shutdown(),
-            commands::remove(),
-            commands::fnfchannel(),
-            commands::removeall(),
-            commands::addplayers(),
-        ],
+        commands,
         prefix_options: poise::PrefixFrameworkOptions {
             prefix: Some("~".into()),
             edit_tracker: Some(poise::EditTracker::for_timespan(Duration::from_secs(60))),
@@ -185,12 +246,39 @@ async fn app() -> Result<(), Error> {
                         events::handle_on_raw_reaction(add_reaction, ctx, data, framework.bot_id.0)
                             .await?
                     },
+                    Event::InteractionCreate {
+                        interaction: serenity::Interaction::MessageComponent(component),
+                    } => {
+                        events::handle_component(component, ctx, data).await?;
+                        panel::handle_button(component, ctx, data).await?;
+                    },
+                    Event::InteractionCreate {
+                        interaction: serenity::Interaction::ModalSubmit(modal),
+                    } => panel::handle_modal(modal, ctx, data).await?,
+                    Event::MessageDelete {
+                        deleted_message_id, ..
+                    } => events::handle_messages_deleted(ctx, data, &[*deleted_message_id]).await?,
+                    Event::MessageDeleteBulk {
+                        multiple_deleted_messages_ids,
+                        ..
+                    } => {
+                        events::handle_messages_deleted(ctx, data, multiple_deleted_messages_ids)
+                            .await?
+                    },
+                    Event::ChannelDelete { channel } => {
+                        events::handle_channel_delete(ctx, data, channel).await?
+                    },
+                    Event::GuildMemberRemoval { guild_id, user, .. } => {
+                        events::handle_member_removal(ctx, data, *guild_id, user.id).await?
+                    },
                     Event::Ready { data_about_bot } => {
                         info!("Connected as {}", data_about_bot.user.tag());
                         info!(
                             "Invite URL = {}",
                             invite_url(&data_about_bot.user, ctx).await?
                         );
+
+                        events::reconcile_reactions(ctx, data, framework.bot_id).await?;
                     },
                     _ => (),
                 }
@@ -217,6 +305,8 @@ async fn app() -> Result<(), Error> {
                 ctx.set_activity(serenity::Activity::playing("Discord Brawl Cup"))
                     .await;
 
+                tokio::spawn(jobs::run(ctx.clone(), db_pool.clone()));
+
                 Ok(Data {
                     db_pool,
                     messages,
@@ -226,7 +316,12 @@ async fn app() -> Result<(), Error> {
         })
         .initialize_owners(true)
         .options(options)
-        .intents(GatewayIntents::non_privileged() | GatewayIntents::MESSAGE_CONTENT)
+        // both privileged intents must be enabled in the developer portal, see the readme
+        .intents(
+            GatewayIntents::non_privileged()
+                | GatewayIntents::MESSAGE_CONTENT
+                | GatewayIntents::GUILD_MEMBERS,
+        )
         .client_settings(|c| c.cache_settings(|s| s.max_messages(100)))
         .build()
         .await?;
@@ -0,0 +1,482 @@
+use poise::serenity_prelude::{
+    self as serenity,
+    ActionRowComponent,
+    ButtonStyle,
+    ChannelId,
+    GuildId,
+    InputTextStyle,
+    InteractionResponseType,
+    MessageComponentInteraction,
+    MessageId,
+    ModalSubmitInteraction,
+    UserId,
+};
+use sqlx::PgPool;
+use tracing::{error, info};
+
+use crate::channels::add_players;
+use crate::datetime::format_timestamp;
+use crate::room::{cancel_room, change_status, roster_embed, RoomStatus, RosterGroup};
+use crate::utils::can_manage_rooms;
+use crate::{Data, Error};
+
+/// Custom IDs of panel components look like `panel:<action>:<room message ID>`, so they
+/// keep working after a restart without us having to remember anything.
+const PANEL_PREFIX: &str = "panel";
+const REASON_INPUT_ID: &str = "reason";
+const CHANNEL_INPUT_ID: &str = "channel";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum PanelAction {
+    Open,
+    Close,
+    Start,
+    Finish,
+    Cancel,
+    Roster,
+    AddPlayers,
+}
+
+impl PanelAction {
+    const ALL: [Self; 7] = [
+        Self::Open,
+        Self::Close,
+        Self::Start,
+        Self::Finish,
+        Self::Cancel,
+        Self::Roster,
+        Self::AddPlayers,
+    ];
+
+    fn id(self) -> &'static str {
+        match self {
+            Self::Open => "open",
+            Self::Close => "close",
+            Self::Start => "start",
+            Self::Finish => "finish",
+            Self::Cancel => "cancel",
+            Self::Roster => "roster",
+            Self::AddPlayers => "addplayers",
+        }
+    }
+
+    fn custom_id(self, message_id: MessageId) -> String {
+        format!("{PANEL_PREFIX}:{}:{}", self.id(), message_id.0)
+    }
+
+    /// The status the action moves a room to, if it changes the status at all.
+    fn status(self) -> Option<RoomStatus> {
+        match self {
+            Self::Open => Some(RoomStatus::Open),
+            Self::Close => Some(RoomStatus::Closed),
+            Self::Start => Some(RoomStatus::Started),
+            Self::Finish => Some(RoomStatus::Finished),
+            Self::Cancel => Some(RoomStatus::Cancelled),
+            Self::Roster | Self::AddPlayers => None,
+        }
+    }
+}
+
+/// Returns the action and room of a panel component's custom ID, or `None` if the
+/// component isn't part of a panel.
+fn parse_custom_id(custom_id: &str) -> Option<(PanelAction, MessageId)> {
+    let mut parts = custom_id.split(':');
+    if parts.next()? != PANEL_PREFIX {
+        return None;
+    }
+
+    let id = parts.next()?;
+    let action = PanelAction::ALL.into_iter().find(|a| a.id() == id)?;
+    let message_id = parts.next()?.parse().ok()?;
+
+    Some((action, MessageId(message_id)))
+}
+
+fn panel_button(
+    b: &mut serenity::CreateButton,
+    message_id: MessageId,
+    status: RoomStatus,
+    action: PanelAction,
+) -> &mut serenity::CreateButton {
+    let enabled = match action.status() {
+        Some(next) => status.can_transition_to(next),
+        None => !matches!(status, RoomStatus::Finished | RoomStatus::Cancelled),
+    };
+
+    b.custom_id(action.custom_id(message_id)).disabled(!enabled)
+}
+
+/// Renders a room's panel. Buttons that don't apply to the room's current status are
+/// disabled.
+async fn render_panel(
+    pool: &PgPool,
+    message_id: MessageId,
+) -> Result<(serenity::CreateEmbed, serenity::CreateComponents), Error> {
+    let room = sqlx::query!(
+        r#"SELECT guild_id, channel_id, num, title, starts_at, status as "status: RoomStatus"
+        FROM message WHERE message_id = $1"#,
+        message_id.0 as i64
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let link = message_id.link(
+        ChannelId(room.channel_id as u64),
+        Some(GuildId(room.guild_id as u64)),
+    );
+
+    let mut description = format!(
+        "**[Go to room]({link})**\nStatus: **{}**",
+        room.status.name()
+    );
+    if let Some(starts_at) = room.starts_at {
+        description.push_str(&format!("\nStarts {}", format_timestamp(starts_at)));
+    }
+
+    let mut embed = serenity::CreateEmbed::default();
+    embed
+        .colour(room.status.colour())
+        .title(match room.title {
+            Some(ref title) => format!("Control panel: Room #{}: {title}", room.num),
+            None => format!("Control panel: Room #{}", room.num),
+        })
+        .description(description);
+
+    let status = room.status;
+    let mut components = serenity::CreateComponents::default();
+    components
+        .create_action_row(|r| {
+            r.create_button(|b| {
+                panel_button(b, message_id, status, PanelAction::Open)
+                    .label("Open")
+                    .style(ButtonStyle::Success)
+            })
+            .create_button(|b| {
+                panel_button(b, message_id, status, PanelAction::Close)
+                    .label("Close")
+                    .style(ButtonStyle::Secondary)
+            })
+            .create_button(|b| {
+                panel_button(b, message_id, status, PanelAction::Start)
+                    .label("Start")
+                    .style(ButtonStyle::Primary)
+            })
+            .create_button(|b| {
+                panel_button(b, message_id, status, PanelAction::Finish)
+                    .label("Finish")
+                    .style(ButtonStyle::Secondary)
+            })
+            .create_button(|b| {
+                panel_button(b, message_id, status, PanelAction::Cancel)
+                    .label("Cancel")
+                    .style(ButtonStyle::Danger)
+            })
+        })
+        .create_action_row(|r| {
+            r.create_button(|b| {
+                panel_button(b, message_id, status, PanelAction::Roster)
+                    .label("Show roster")
+                    .style(ButtonStyle::Secondary)
+            })
+            .create_button(|b| {
+                panel_button(b, message_id, status, PanelAction::AddPlayers)
+                    .label("Add players")
+                    .style(ButtonStyle::Primary)
+            })
+        });
+
+    Ok((embed, components))
+}
+
+/// Sends a room's control panel to the guild's panel channel, or to the host if the guild
+/// doesn't have one. A previously sent panel for the room is deleted.
+pub async fn send_panel(
+    ctx: &serenity::Context,
+    data: &Data,
+    message_id: MessageId,
+) -> Result<(), Error> {
+    let room = sqlx::query!(
+        "SELECT guild_id, host_id, panel_channel_id, panel_message_id FROM message
+        WHERE message_id = $1",
+        message_id.0 as i64
+    )
+    .fetch_one(&data.db_pool)
+    .await?;
+
+    let panel_channel = data
+        .guild_configs
+        .get(&(room.guild_id as u64))
+        .and_then(|c| c.panel_channel_id);
+
+    let channel_id = match (panel_channel, room.host_id) {
+        (Some(id), _) => ChannelId(id),
+        (None, Some(host_id)) => UserId(host_id as u64).create_dm_channel(ctx).await?.id,
+        (None, None) => return Err("The room has no host and the panel channel is not set.".into()),
+    };
+
+    let (embed, components) = render_panel(&data.db_pool, message_id).await?;
+    let panel = channel_id
+        .send_message(ctx, |m| m.set_embed(embed).set_components(components))
+        .await?;
+
+    if let (Some(old_channel), Some(old_message)) = (room.panel_channel_id, room.panel_message_id) {
+        // the old panel still works, it just wouldn't be kept up to date anymore
+        if let Err(e) = ChannelId(old_channel as u64)
+            .delete_message(ctx, old_message as u64)
+            .await
+        {
+            info!("unable to delete old panel of room {}: {e}", message_id.0);
+        }
+    }
+
+    sqlx::query!(
+        "UPDATE message SET panel_channel_id = $1, panel_message_id = $2 WHERE message_id = $3",
+        channel_id.0 as i64,
+        panel.id.0 as i64,
+        message_id.0 as i64
+    )
+    .execute(&data.db_pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Re-renders a room's panel, if it has one, after its status changed.
+pub async fn update_panel(
+    ctx: &serenity::Context,
+    pool: &PgPool,
+    message_id: MessageId,
+) -> Result<(), Error> {
+    let room = sqlx::query!(
+        "SELECT panel_channel_id, panel_message_id FROM message WHERE message_id = $1",
+        message_id.0 as i64
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let (channel_id, panel_id) = match (room.panel_channel_id, room.panel_message_id) {
+        (Some(c), Some(m)) => (ChannelId(c as u64), MessageId(m as u64)),
+        _ => return Ok(()),
+    };
+
+    let (embed, components) = render_panel(pool, message_id).await?;
+    channel_id
+        .edit_message(ctx, panel_id, |m| {
+            m.set_embed(embed).set_components(components)
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// Makes sure whoever pressed a panel button may manage the room, returning the error to
+/// show them otherwise.
+async fn check_access(
+    ctx: &serenity::Context,
+    data: &Data,
+    message_id: MessageId,
+    user_id: UserId,
+) -> Result<(), String> {
+    let guild_id = sqlx::query!(
+        "SELECT guild_id FROM message WHERE message_id = $1",
+        message_id.0 as i64
+    )
+    .fetch_optional(&data.db_pool)
+    .await
+    .map_err(|e| e.to_string())?
+    .map(|r| GuildId(r.guild_id as u64))
+    .ok_or("This room doesn't exist anymore.")?;
+
+    match can_manage_rooms(ctx, data, guild_id, user_id).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err("Only hosts can use this panel.".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Handles the buttons of control panels.
+pub async fn handle_button(
+    component: &MessageComponentInteraction,
+    ctx: &serenity::Context,
+    data: &Data,
+) -> Result<(), Error> {
+    let (action, message_id) = match parse_custom_id(&component.data.custom_id) {
+        Some(parsed) => parsed,
+        None => return Ok(()),
+    };
+
+    if let Err(reason) = check_access(ctx, data, message_id, component.user.id).await {
+        component
+            .create_interaction_response(ctx, |r| {
+                r.interaction_response_data(|d| d.ephemeral(true).content(reason))
+            })
+            .await?;
+        return Ok(());
+    }
+
+    // cancelling and adding players need more input, which we ask for in a modal
+    let modal = match action {
+        PanelAction::Cancel => Some(("Cancel room", REASON_INPUT_ID, "Reason shown to players")),
+        PanelAction::AddPlayers => Some((
+            "Add players to a channel",
+            CHANNEL_INPUT_ID,
+            "Channel name, mention or ID",
+        )),
+        _ => None,
+    };
+
+    if let Some((title, input_id, label)) = modal {
+        component
+            .create_interaction_response(ctx, |r| {
+                r.kind(InteractionResponseType::Modal)
+                    .interaction_response_data(|d| {
+                        d.custom_id(action.custom_id(message_id))
+                            .title(title)
+                            .components(|c| {
+                                c.create_action_row(|r| {
+                                    r.create_input_text(|t| {
+                                        t.custom_id(input_id)
+                                            .label(label)
+                                            .style(InputTextStyle::Short)
+                                            .required(action == PanelAction::AddPlayers)
+                                    })
+                                })
+                            })
+                    })
+            })
+            .await?;
+        return Ok(());
+    }
+
+    component
+        .create_interaction_response(ctx, |r| {
+            r.kind(InteractionResponseType::DeferredChannelMessageWithSource)
+                .interaction_response_data(|d| d.ephemeral(true))
+        })
+        .await?;
+
+    if action == PanelAction::Roster {
+        let embed = roster_embed(&data.db_pool, message_id, None, true).await?;
+        component
+            .edit_original_interaction_response(ctx, |r| r.set_embed(embed))
+            .await?;
+        return Ok(());
+    }
+
+    let next = match action.status() {
+        Some(next) => next,
+        None => return Ok(()),
+    };
+
+    let reply = match change_status(ctx, &data.db_pool, message_id, next).await {
+        Ok(text) => text,
+        Err(e) => e.to_string(),
+    };
+
+    component
+        .edit_original_interaction_response(ctx, |r| r.content(reply))
+        .await?;
+
+    Ok(())
+}
+
+/// Handles the modals panel buttons open for cancelling a room and adding its players to a
+/// channel.
+pub async fn handle_modal(
+    modal: &ModalSubmitInteraction,
+    ctx: &serenity::Context,
+    data: &Data,
+) -> Result<(), Error> {
+    let (action, message_id) = match parse_custom_id(&modal.data.custom_id) {
+        Some(parsed) => parsed,
+        None => return Ok(()),
+    };
+
+    modal
+        .create_interaction_response(ctx, |r| {
+            r.kind(InteractionResponseType::DeferredChannelMessageWithSource)
+                .interaction_response_data(|d| d.ephemeral(true))
+        })
+        .await?;
+
+    let input = modal
+        .data
+        .components
+        .iter()
+        .flat_map(|r| &r.components)
+        .find_map(|c| match c {
+            ActionRowComponent::InputText(t) => Some(t.value.trim().to_string()),
+            _ => None,
+        })
+        .unwrap_or_default();
+
+    let res = match check_access(ctx, data, message_id, modal.user.id).await {
+        Err(reason) => Err(reason.into()),
+        Ok(()) => match action {
+            PanelAction::Cancel => {
+                let reason = Some(input).filter(|r| !r.is_empty());
+                cancel_room(ctx, &data.db_pool, message_id, reason).await
+            },
+            PanelAction::AddPlayers => {
+                add_players_to(ctx, data, message_id, &input, modal.channel_id).await
+            },
+            _ => return Ok(()),
+        },
+    };
+
+    let reply = match res {
+        Ok(text) => text,
+        Err(e) => {
+            error!("error handling panel modal for room {}: {e}", message_id.0);
+            e.to_string()
+        },
+    };
+
+    modal
+        .edit_original_interaction_response(ctx, |r| r.content(reply))
+        .await?;
+
+    Ok(())
+}
+
+/// Adds a room's players to the channel a host named in the panel.
+async fn add_players_to(
+    ctx: &serenity::Context,
+    data: &Data,
+    message_id: MessageId,
+    input: &str,
+    report_channel: ChannelId,
+) -> Result<String, Error> {
+    let guild_id = sqlx::query!(
+        "SELECT guild_id FROM message WHERE message_id = $1",
+        message_id.0 as i64
+    )
+    .fetch_one(&data.db_pool)
+    .await?
+    .guild_id;
+
+    let channels = GuildId(guild_id as u64).channels(ctx).await?;
+    let channel_id = serenity::parse_channel(input)
+        .or_else(|| input.parse().ok())
+        .map(ChannelId)
+        .filter(|id| channels.contains_key(id))
+        .or_else(|| {
+            let name = input.trim_start_matches('#');
+            channels
+                .values()
+                .find(|c| c.name.eq_ignore_ascii_case(name))
+                .map(|c| c.id)
+        })
+        .ok_or_else(|| format!("Unable to find a channel called `{input}` in this server."))?;
+
+    add_players(
+        ctx,
+        &data.db_pool,
+        message_id,
+        channel_id,
+        RosterGroup::Both,
+        false,
+        Some(report_channel),
+    )
+    .await
+}
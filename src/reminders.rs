@@ -0,0 +1,147 @@
+use chrono::{DateTime, Utc};
+use poise::serenity_prelude::{self as serenity, ChannelId, GuildId, MessageId, UserId};
+use sqlx::PgPool;
+use tracing::{error, info};
+
+use crate::datetime::format_timestamp;
+use crate::jobs::{self, Job};
+use crate::room::{get_roster, RoomStatus};
+use crate::utils::{dm_user, get_room_capacity, split_message};
+use crate::Error;
+
+/// Minutes before the start of a room at which players are reminded, unless the guild
+/// configured its own.
+pub const DEFAULT_REMINDER_OFFSETS: [i32; 2] = [30, 5];
+
+/// Replaces any pending reminders for a room with ones based on the guild's reminder
+/// offsets. Reminders that would already be due are skipped.
+pub async fn schedule_reminders(
+    pool: &PgPool,
+    guild_id: GuildId,
+    message_id: MessageId,
+    starts_at: DateTime<Utc>,
+) -> Result<(), Error> {
+    let offsets = sqlx::query!(
+        "SELECT reminder_offsets FROM config WHERE guild_id = $1",
+        guild_id.0 as i64
+    )
+    .fetch_optional(pool)
+    .await?
+    .and_then(|r| r.reminder_offsets)
+    .unwrap_or_else(|| DEFAULT_REMINDER_OFFSETS.to_vec());
+
+    let mut tx = pool.begin().await?;
+
+    jobs::unschedule(&mut tx, message_id, "reminder").await?;
+
+    let now = Utc::now();
+    for minutes_before in offsets {
+        let remind_at = starts_at - chrono::Duration::minutes(minutes_before as i64);
+        if remind_at <= now {
+            continue;
+        }
+
+        let job = Job::Reminder {
+            message_id: message_id.0,
+            minutes_before,
+        };
+        jobs::schedule(&mut tx, job, remind_at).await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// DMs everyone signed up for a room that it starts soon, and pings the main roster in
+/// the room's channel if the guild wants that. Every DM and ping is recorded as a step of
+/// the job, so a retried reminder only sends what is still missing.
+pub async fn send_reminder(
+    ctx: &serenity::Context,
+    pool: &PgPool,
+    job_id: i64,
+    message_id: MessageId,
+) -> Result<(), Error> {
+    let room = sqlx::query!(
+        r#"SELECT guild_id, channel_id, num, starts_at, status as "status: RoomStatus"
+        FROM message WHERE message_id = $1"#,
+        message_id.0 as i64
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let starts_at = match room.starts_at {
+        Some(t) if t > Utc::now() => t,
+        // we were offline for too long, the reminder is pointless now
+        _ => return Ok(()),
+    };
+
+    if !matches!(room.status, RoomStatus::Open | RoomStatus::Closed) {
+        return Ok(());
+    }
+
+    let (guild_id, room_num) = (room.guild_id, room.num);
+    let channel_id = ChannelId(room.channel_id as u64);
+
+    let roster = get_roster(pool, message_id).await?;
+    let capacity = get_room_capacity(message_id, pool).await?;
+    let main = capacity.main as usize;
+
+    for (pos, user_id) in roster.iter().enumerate() {
+        // claimed before sending, a player missing a reminder beats getting it twice
+        if !jobs::claim_step(pool, job_id, &format!("dm:{user_id}")).await? {
+            continue;
+        }
+
+        let content = if pos < main {
+            format!(
+                "Reminder: Room #{} starts {}.",
+                room_num,
+                format_timestamp(starts_at)
+            )
+        } else {
+            format!(
+                "Reminder: Room #{} starts {}. You are reserve {}/{}.",
+                room_num,
+                format_timestamp(starts_at),
+                pos - main + 1,
+                capacity.reserve
+            )
+        };
+
+        // failures are logged by `dm_user`, the remaining players should still be reminded
+        let _ = dm_user(ctx, UserId(*user_id as u64), content).await;
+    }
+
+    let ping = sqlx::query!(
+        "SELECT reminder_ping FROM config WHERE guild_id = $1",
+        guild_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .is_some_and(|r| r.reminder_ping);
+
+    if ping && !roster.is_empty() {
+        let mut lines = vec![format!(
+            "Room #{} starts {}!",
+            room_num,
+            format_timestamp(starts_at)
+        )];
+        lines.extend(roster.iter().take(main).map(|u| format!("<@{u}>")));
+
+        for (i, content) in split_message(&lines, " ").into_iter().enumerate() {
+            // claimed before sending as well, a missed ping beats pinging the roster twice
+            if !jobs::claim_step(pool, job_id, &format!("ping:{i}")).await? {
+                continue;
+            }
+
+            if let Err(e) = channel_id.say(ctx, content).await {
+                error!("error pinging players of room {}: {e}", message_id.0);
+            }
+        }
+    }
+
+    info!("sent reminders for room {}", message_id.0);
+
+    Ok(())
+}
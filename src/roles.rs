@@ -0,0 +1,343 @@
+use poise::serenity_prelude::{
+    self as serenity,
+    ChannelId,
+    GuildId,
+    Mentionable,
+    MessageId,
+    RoleId,
+    UserId,
+};
+use sqlx::PgPool;
+use tracing::{error, info};
+
+use crate::room::get_roster_split;
+use crate::utils::{is_discord_error, run_batched, Progress, UNKNOWN_MEMBER, UNKNOWN_ROLE};
+use crate::Error;
+
+/// Returns the role given to the main roster of the room's guild, if one is set.
+pub async fn get_participant_role(
+    pool: &PgPool,
+    message_id: MessageId,
+) -> Result<Option<RoleId>, Error> {
+    let record = sqlx::query!(
+        "SELECT c.participant_role_id FROM message m JOIN config c ON c.guild_id = m.guild_id
+        WHERE m.message_id = $1",
+        message_id.0 as i64
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(record
+        .and_then(|r| r.participant_role_id)
+        .map(|id| RoleId(id as u64)))
+}
+
+/// Makes the participant role holders of a room match its main roster, returning a report
+/// of what changed. Ended rooms, and rooms of guilds without a participant role, end up
+/// with no holders at all.
+pub async fn sync_participant_role(
+    ctx: &serenity::Context,
+    pool: &PgPool,
+    message_id: MessageId,
+    report_channel: Option<ChannelId>,
+) -> Result<String, Error> {
+    let room = sqlx::query!(
+        "SELECT num, guild_id, status IN ('open', 'closed', 'started') as \"active!\"
+        FROM message WHERE message_id = $1",
+        message_id.0 as i64
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let role_id = get_participant_role(pool, message_id).await?;
+
+    let holders = match role_id {
+        Some(role_id) if room.active => {
+            let (main, _) = get_roster_split(pool, message_id).await?;
+            main.into_iter()
+                .map(|u| (UserId(u as u64), role_id))
+                .collect()
+        },
+        _ => Vec::new(),
+    };
+
+    let guild_id = GuildId(room.guild_id as u64);
+    set_role_holders(
+        ctx,
+        pool,
+        guild_id,
+        room.num,
+        message_id,
+        holders,
+        report_channel,
+    )
+    .await
+}
+
+/// Takes the participant role from everyone a room gave it to, for when the room is
+/// removed. Fails if anyone kept it, so the room's grants aren't lost.
+pub async fn strip_participant_role(
+    ctx: &serenity::Context,
+    pool: &PgPool,
+    message_id: MessageId,
+) -> Result<(), Error> {
+    let room = sqlx::query!(
+        "SELECT num, guild_id FROM message WHERE message_id = $1",
+        message_id.0 as i64
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let guild_id = GuildId(room.guild_id as u64);
+    set_role_holders(ctx, pool, guild_id, room.num, message_id, Vec::new(), None).await?;
+
+    let left = sqlx::query!(
+        r#"SELECT count(*) as "count!" FROM role_grant WHERE message_id = $1"#,
+        message_id.0 as i64
+    )
+    .fetch_one(pool)
+    .await?
+    .count;
+
+    if left > 0 {
+        return Err(format!(
+            "Couldn't take the participant role from {left} player(s) of Room #{}.",
+            room.num
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Gives and takes the participant role until exactly `holders` have it on behalf of the
+/// room, returning a report of what changed.
+async fn set_role_holders(
+    ctx: &serenity::Context,
+    pool: &PgPool,
+    guild_id: GuildId,
+    room_num: i32,
+    message_id: MessageId,
+    holders: Vec<(UserId, RoleId)>,
+    report_channel: Option<ChannelId>,
+) -> Result<String, Error> {
+    let grants = sqlx::query!(
+        "SELECT user_id, role_id FROM role_grant WHERE message_id = $1",
+        message_id.0 as i64
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|r| (UserId(r.user_id as u64), RoleId(r.role_id as u64)))
+    .collect::<Vec<_>>();
+
+    let to_give = holders
+        .iter()
+        .filter(|h| !grants.contains(h))
+        .copied()
+        .collect::<Vec<_>>();
+    let to_strip = grants
+        .iter()
+        .filter(|g| !holders.contains(g))
+        .copied()
+        .collect::<Vec<_>>();
+
+    if to_give.is_empty() && to_strip.is_empty() {
+        return Ok(String::new());
+    }
+
+    let mut progress = Progress::start(
+        ctx,
+        report_channel,
+        format!("Updating participant roles of Room #{room_num}"),
+        to_give.len() + to_strip.len(),
+    )
+    .await;
+
+    let mut report = String::new();
+
+    let given = run_batched(to_give, &mut progress, |(user_id, role_id)| {
+        give_role(
+            ctx.clone(),
+            pool.clone(),
+            guild_id,
+            message_id,
+            user_id,
+            role_id,
+        )
+    })
+    .await;
+    for ((user_id, role_id), result) in given {
+        let line = match result {
+            Ok(true) => format!("Gave {} {}", user_id.mention(), role_id.mention()),
+            Ok(false) => format!(
+                "Skipped {}, who already has {}",
+                user_id.mention(),
+                role_id.mention()
+            ),
+            Err(e) => format!("Failed to give {} the role: {e}", user_id.mention()),
+        };
+        report.push_str(&line);
+        report.push('\n');
+    }
+
+    let stripped = run_batched(to_strip, &mut progress, |(user_id, role_id)| {
+        strip_role(
+            ctx.clone(),
+            pool.clone(),
+            guild_id,
+            message_id,
+            user_id,
+            role_id,
+        )
+    })
+    .await;
+    for ((user_id, role_id), result) in stripped {
+        let line = match result {
+            Ok(()) => format!("Took {} from {}", role_id.mention(), user_id.mention()),
+            Err(e) => format!("Failed to take the role from {}: {e}", user_id.mention()),
+        };
+        report.push_str(&line);
+        report.push('\n');
+    }
+
+    progress.finish(&report).await;
+
+    Ok(report)
+}
+
+/// Syncs the participant role after a room's roster changed, if the room's players already
+/// hold it. Errors are only logged, as the roster change itself went through.
+pub async fn refresh_participant_role(
+    ctx: &serenity::Context,
+    pool: &PgPool,
+    message_id: MessageId,
+) {
+    let handed_out = sqlx::query!(
+        r#"SELECT status IN ('closed', 'started')
+        OR exists (SELECT 1 FROM role_grant r WHERE r.message_id = m.message_id) as "handed_out!"
+        FROM message m WHERE message_id = $1"#,
+        message_id.0 as i64
+    )
+    .fetch_one(pool)
+    .await;
+
+    match handed_out {
+        Ok(r) if r.handed_out => {
+            if let Err(e) = sync_participant_role(ctx, pool, message_id, None).await {
+                error!(
+                    "error syncing participant role of room {}: {e}",
+                    message_id.0
+                );
+            }
+        },
+        Ok(_) => (),
+        Err(e) => error!(
+            "error syncing participant role of room {}: {e}",
+            message_id.0
+        ),
+    }
+}
+
+/// Gives a player the role on behalf of a room. Returns `false` without touching anything if
+/// they already hold it without the bot having given it, as staff did so.
+async fn give_role(
+    ctx: serenity::Context,
+    pool: PgPool,
+    guild_id: GuildId,
+    message_id: MessageId,
+    user_id: UserId,
+    role_id: RoleId,
+) -> Result<bool, Error> {
+    let member = guild_id.member(&ctx, user_id).await?;
+
+    if member.roles.contains(&role_id) {
+        let granted = sqlx::query!(
+            r#"SELECT exists (SELECT 1 FROM role_grant WHERE user_id = $1 AND role_id = $2)
+            as "granted!""#,
+            user_id.0 as i64,
+            role_id.0 as i64,
+        )
+        .fetch_one(&pool)
+        .await?
+        .granted;
+
+        if !granted {
+            return Ok(false);
+        }
+    } else {
+        ctx.http
+            .add_member_role(guild_id.0, user_id.0, role_id.0, Some("Room participant"))
+            .await?;
+    }
+
+    sqlx::query!(
+        "INSERT INTO role_grant (message_id, user_id, role_id) VALUES ($1, $2, $3)
+        ON CONFLICT DO NOTHING",
+        message_id.0 as i64,
+        user_id.0 as i64,
+        role_id.0 as i64,
+    )
+    .execute(&pool)
+    .await?;
+
+    info!(
+        "gave user {} role {} for room {}",
+        user_id, role_id, message_id.0
+    );
+
+    Ok(true)
+}
+
+/// Takes back the role a room gave a player. The role itself is only removed once no other
+/// room needs it.
+async fn strip_role(
+    ctx: serenity::Context,
+    pool: PgPool,
+    guild_id: GuildId,
+    message_id: MessageId,
+    user_id: UserId,
+    role_id: RoleId,
+) -> Result<(), Error> {
+    let still_needed = sqlx::query!(
+        r#"SELECT exists (
+            SELECT 1 FROM role_grant WHERE user_id = $1 AND role_id = $2 AND message_id <> $3
+        ) as "still_needed!""#,
+        user_id.0 as i64,
+        role_id.0 as i64,
+        message_id.0 as i64,
+    )
+    .fetch_one(&pool)
+    .await?
+    .still_needed;
+
+    if !still_needed {
+        let removed = ctx
+            .http
+            .remove_member_role(
+                guild_id.0,
+                user_id.0,
+                role_id.0,
+                Some("No longer on a room's main roster"),
+            )
+            .await;
+
+        // members who left and deleted roles have nothing left to strip
+        match removed {
+            Ok(()) => (),
+            Err(e) if is_discord_error(&e, &[UNKNOWN_MEMBER, UNKNOWN_ROLE]) => (),
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    sqlx::query!(
+        "DELETE FROM role_grant WHERE message_id = $1 AND user_id = $2 AND role_id = $3",
+        message_id.0 as i64,
+        user_id.0 as i64,
+        role_id.0 as i64,
+    )
+    .execute(&pool)
+    .await?;
+
+    Ok(())
+}
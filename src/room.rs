@@ -0,0 +1,1157 @@
+use chrono::{DateTime, Utc};
+use poise::serenity_prelude::{
+    self as serenity,
+    ButtonStyle,
+    ChannelId,
+    GuildId,
+    Mentionable,
+    MessageId,
+    UserId,
+};
+use sqlx::postgres::{PgHasArrayType, PgTypeInfo};
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::{error, info};
+
+use crate::datetime::format_timestamp;
+use crate::history::{record_event, SignupEventKind};
+use crate::utils::{dm_user, get_room_capacity, notify_user, spots_footer, Capacity};
+use crate::{channels, jobs, panel, roles, Data, Error, EMBED_COLOUR};
+
+pub const DEFAULT_CLOSE_LEAD_MINUTES: i32 = 0;
+pub const DEFAULT_ROOM_DURATION_MINUTES: i32 = 120;
+pub const DEFAULT_SIGNUP_MODE: SignupMode = SignupMode::Buttons;
+
+pub const JOIN_BUTTON_ID: &str = "room_join";
+pub const LEAVE_BUTTON_ID: &str = "room_leave";
+pub const ROSTER_BUTTON_ID: &str = "room_roster";
+
+/// How players sign up for a room. Rooms keep the mode they were hosted with.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, sqlx::Type, poise::ChoiceParameter)]
+#[sqlx(type_name = "signup_mode", rename_all = "lowercase")]
+pub enum SignupMode {
+    #[name = "reactions"]
+    Reactions,
+    #[name = "buttons"]
+    Buttons,
+}
+
+/// The part of a room's roster a bulk action applies to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum RosterGroup {
+    #[name = "main"]
+    Main,
+    #[name = "reserves"]
+    Reserves,
+    #[name = "both"]
+    Both,
+}
+
+impl RosterGroup {
+    pub fn includes_main(self) -> bool {
+        self != Self::Reserves
+    }
+
+    pub fn includes_reserves(self) -> bool {
+        self != Self::Main
+    }
+}
+
+/// Lifecycle of a room. Signups are only accepted while a room is open.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "room_status", rename_all = "lowercase")]
+pub enum RoomStatus {
+    Open,
+    Closed,
+    Started,
+    Finished,
+    Cancelled,
+}
+
+impl PgHasArrayType for RoomStatus {
+    fn array_type_info() -> PgTypeInfo {
+        PgTypeInfo::with_name("_room_status")
+    }
+}
+
+impl RoomStatus {
+    /// Returns true if a room may move from this status to `next`.
+    pub fn can_transition_to(self, next: Self) -> bool {
+        use RoomStatus::*;
+
+        matches!(
+            (self, next),
+            (Open, Closed | Started | Cancelled)
+                | (Closed, Open | Started | Cancelled)
+                | (Started, Finished | Cancelled)
+        )
+    }
+
+    /// Returns true if players may still deregister from the room.
+    pub fn accepts_withdrawals(self) -> bool {
+        matches!(self, Self::Open | Self::Closed)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Open => "open",
+            Self::Closed => "closed",
+            Self::Started => "started",
+            Self::Finished => "finished",
+            Self::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn colour(self) -> u32 {
+        match self {
+            Self::Open => EMBED_COLOUR,
+            Self::Closed => 0xE67E22,
+            Self::Started => 0x2ECC71,
+            Self::Finished => 0x95A5A6,
+            Self::Cancelled => 0xE74C3C,
+        }
+    }
+
+    pub fn title(self, room_num: i32, custom: Option<&str>) -> String {
+        let name = match custom {
+            Some(title) => format!("Room #{room_num}: {title}"),
+            None => format!("Room #{room_num}"),
+        };
+
+        match self {
+            Self::Open => name,
+            Self::Closed => format!("{name} (registrations closed)"),
+            Self::Started => format!("{name} (in progress)"),
+            Self::Finished => format!("{name} (finished)"),
+            Self::Cancelled => format!("~~{name}~~ (cancelled)"),
+        }
+    }
+
+    pub fn footer(self, capacity: Capacity, signups: i64, mode: SignupMode) -> String {
+        match self {
+            Self::Open => spots_footer(capacity, signups, mode),
+            Self::Closed => format!("Registrations closed | {signups} signed up"),
+            Self::Started => "This room has started".to_string(),
+            Self::Finished => "This room has finished".to_string(),
+            Self::Cancelled => "This room was cancelled".to_string(),
+        }
+    }
+}
+
+pub fn room_description(host_id: Option<UserId>, starts_at: DateTime<Utc>) -> String {
+    match host_id {
+        Some(host_id) => format!(
+            "{} is hosting a room at **{}!**",
+            host_id.mention(),
+            format_timestamp(starts_at)
+        ),
+        None => format!(
+            "A room is being hosted at **{}!**",
+            format_timestamp(starts_at)
+        ),
+    }
+}
+
+/// Builds the buttons of a room that takes signups through buttons. Rooms that are over
+/// don't have any.
+pub fn room_buttons(status: RoomStatus) -> serenity::CreateComponents {
+    let mut components = serenity::CreateComponents::default();
+
+    if matches!(status, RoomStatus::Finished | RoomStatus::Cancelled) {
+        return components;
+    }
+
+    components.create_action_row(|r| {
+        r.create_button(|b| {
+            b.custom_id(JOIN_BUTTON_ID)
+                .label("Join")
+                .style(ButtonStyle::Success)
+                .disabled(status != RoomStatus::Open)
+        })
+        .create_button(|b| {
+            b.custom_id(LEAVE_BUTTON_ID)
+                .label("Leave")
+                .style(ButtonStyle::Danger)
+                .disabled(!status.accepts_withdrawals())
+        })
+        .create_button(|b| {
+            b.custom_id(ROSTER_BUTTON_ID)
+                .label("Show roster")
+                .style(ButtonStyle::Secondary)
+        })
+    });
+
+    components
+}
+
+/// Re-renders a room's embed from the database.
+///
+/// Rooms hosted before start times were stored keep their original description.
+pub async fn update_room_embed(
+    ctx: &serenity::Context,
+    pool: &PgPool,
+    message_id: MessageId,
+) -> Result<(), Error> {
+    let room = sqlx::query!(
+        r#"SELECT channel_id, num, main_slots, reserve_slots, status as "status: RoomStatus",
+        signup_mode as "signup_mode: SignupMode", cancel_reason, host_id, title, starts_at, (SELECT COUNT(*) FROM signup s WHERE s.message_id = m.message_id) as "signups!"
+        FROM message m WHERE message_id = $1"#,
+        message_id.0 as i64
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let channel_id = ChannelId(room.channel_id as u64);
+
+    let mut msg = if let Some(m) = ctx.cache.message(channel_id, message_id) {
+        m
+    } else {
+        channel_id.message(&ctx, message_id).await?
+    };
+
+    if let Some(e) = msg.embeds.first() {
+        let mut embed = serenity::CreateEmbed::default();
+
+        embed
+            .colour(room.status.colour())
+            .title(room.status.title(room.num, room.title.as_deref()));
+
+        if let Some(starts_at) = room.starts_at {
+            let host_id = room.host_id.map(|i| UserId(i as u64));
+            embed.description(room_description(host_id, starts_at));
+        } else if let Some(ref desc) = e.description {
+            embed.description(desc);
+        }
+
+        if room.status == RoomStatus::Cancelled {
+            if let Some(ref reason) = room.cancel_reason {
+                embed.field("Reason", reason, false);
+            }
+        }
+
+        let capacity = Capacity {
+            main: room.main_slots,
+            reserve: room.reserve_slots,
+        };
+
+        let footer = room.status.footer(capacity, room.signups, room.signup_mode);
+        embed.footer(|f| f.text(footer));
+
+        msg.edit(&ctx, |m| {
+            if room.signup_mode == SignupMode::Buttons {
+                m.set_components(room_buttons(room.status));
+            }
+            m.set_embed(embed)
+        })
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Moves a room to `next` if its current status is one of `from`, then updates its embed
+/// and reactions. Returns false if the room wasn't in any of the `from` statuses.
+pub async fn transition(
+    ctx: &serenity::Context,
+    pool: &PgPool,
+    message_id: MessageId,
+    from: &[RoomStatus],
+    next: RoomStatus,
+) -> Result<bool, Error> {
+    let record = sqlx::query!(
+        r#"UPDATE message SET status = $1 WHERE message_id = $2 AND status = ANY($3)
+        RETURNING channel_id, signup_mode as "signup_mode: SignupMode", panel_channel_id"#,
+        next as RoomStatus,
+        message_id.0 as i64,
+        from as &[RoomStatus],
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    let (channel_id, mode, panel_channel) = match record {
+        Some(r) => (
+            ChannelId(r.channel_id as u64),
+            r.signup_mode,
+            r.panel_channel_id.map(|c| ChannelId(c as u64)),
+        ),
+        None => return Ok(false),
+    };
+
+    info!("room {} is now {}", message_id.0, next.name());
+
+    // the status change already happened, so failing to reflect it shouldn't fail the caller
+    if let Err(e) = update_room_embed(ctx, pool, message_id).await {
+        error!("error updating embed of room {}: {e}", message_id.0);
+    }
+
+    if let Err(e) = panel::update_panel(ctx, pool, message_id).await {
+        error!("error updating panel of room {}: {e}", message_id.0);
+    }
+
+    if mode == SignupMode::Reactions {
+        if let Err(e) = sync_reactions(ctx, channel_id, message_id, next).await {
+            error!("error updating reactions of room {}: {e}", message_id.0);
+        }
+    }
+
+    let channels = match next {
+        RoomStatus::Closed | RoomStatus::Started => {
+            channels::create_room_channels(ctx, pool, message_id).await
+        },
+        RoomStatus::Finished | RoomStatus::Cancelled => {
+            channels::cleanup_room_channels(ctx, pool, message_id).await
+        },
+        RoomStatus::Open => Ok(()),
+    };
+    if let Err(e) = channels {
+        error!("error updating channels of room {}: {e}", message_id.0);
+    }
+
+    // the main roster is settled once registration closes, so that's when the role is handed
+    // out. the report goes next to the room's control panel
+    if next != RoomStatus::Open {
+        if let Err(e) = roles::sync_participant_role(ctx, pool, message_id, panel_channel).await {
+            error!(
+                "error syncing participant role of room {}: {e}",
+                message_id.0
+            );
+        }
+    }
+
+    Ok(true)
+}
+
+/// Moves a room to `next` on behalf of a host, returning a confirmation to show them.
+pub async fn change_status(
+    ctx: &serenity::Context,
+    pool: &PgPool,
+    message_id: MessageId,
+    next: RoomStatus,
+) -> Result<String, Error> {
+    let record = sqlx::query!(
+        r#"SELECT num, status as "status: RoomStatus" FROM message WHERE message_id = $1"#,
+        message_id.0 as i64
+    )
+    .fetch_one(pool)
+    .await?;
+
+    if !record.status.can_transition_to(next) {
+        return Err(format!(
+            "Room #{} is {} and can't be marked as {}.",
+            record.num,
+            record.status.name(),
+            next.name()
+        )
+        .into());
+    }
+
+    // only update if nobody changed the status in the meantime
+    if !transition(ctx, pool, message_id, &[record.status], next).await? {
+        return Err("The room's status changed while updating it, please try again.".into());
+    }
+
+    Ok(format!("Room #{} is now {}.", record.num, next.name()))
+}
+
+/// Cancels a room on behalf of a host and DMs everyone signed up, returning a summary to
+/// show the host.
+pub async fn cancel_room(
+    ctx: &serenity::Context,
+    pool: &PgPool,
+    message_id: MessageId,
+    reason: Option<String>,
+) -> Result<String, Error> {
+    let record = sqlx::query!(
+        r#"SELECT num, status as "status: RoomStatus" FROM message WHERE message_id = $1"#,
+        message_id.0 as i64
+    )
+    .fetch_one(pool)
+    .await?;
+
+    if !record.status.can_transition_to(RoomStatus::Cancelled) {
+        return Err(format!(
+            "Room #{} is {} and can't be cancelled.",
+            record.num,
+            record.status.name()
+        )
+        .into());
+    }
+
+    const RACED: &str = "The room's status changed while updating it, please try again.";
+
+    // the reason has to be stored before the transition re-renders the embed
+    let stored = sqlx::query!(
+        "UPDATE message SET cancel_reason = $1 WHERE message_id = $2 AND status = $3",
+        reason,
+        message_id.0 as i64,
+        record.status as RoomStatus,
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    if stored == 0 {
+        return Err(RACED.into());
+    }
+
+    let from = [record.status];
+    if !transition(ctx, pool, message_id, &from, RoomStatus::Cancelled).await? {
+        // a room that didn't get cancelled mustn't keep the reason
+        sqlx::query!(
+            "UPDATE message SET cancel_reason = NULL
+            WHERE message_id = $1 AND status <> 'cancelled'",
+            message_id.0 as i64
+        )
+        .execute(pool)
+        .await?;
+
+        return Err(RACED.into());
+    }
+
+    jobs::unschedule_all(pool, message_id).await?;
+
+    let content = match reason {
+        Some(ref reason) => format!("Room #{} has been cancelled: {reason}", record.num),
+        None => format!("Room #{} has been cancelled.", record.num),
+    };
+
+    let roster = get_roster(pool, message_id).await?;
+    let mut notified = 0;
+    for user_id in &roster {
+        if dm_user(ctx, UserId(*user_id as u64), &content)
+            .await
+            .is_ok()
+        {
+            notified += 1;
+        }
+    }
+
+    Ok(format!(
+        "Cancelled Room #{} and notified {notified}/{} signed up players.",
+        record.num,
+        roster.len()
+    ))
+}
+
+/// Cancels a room whose message is gone, along with its pending jobs, and lets its players
+/// know. Signups are kept for the record, but no longer count towards the room limit.
+pub async fn orphan_room(
+    ctx: &serenity::Context,
+    data: &Data,
+    message_id: MessageId,
+    reason: &str,
+) -> Result<(), Error> {
+    if data.messages.remove(&message_id.0).is_none() {
+        return Ok(());
+    }
+
+    // the embed is gone, so unlike `transition` there is nothing to re-render
+    let record = sqlx::query!(
+        "UPDATE message SET status = 'cancelled', cancel_reason = COALESCE(cancel_reason, $2)
+        WHERE message_id = $1 AND status IN ('open', 'closed', 'started')
+        RETURNING num",
+        message_id.0 as i64,
+        reason,
+    )
+    .fetch_optional(&data.db_pool)
+    .await?;
+
+    jobs::unschedule_all(&data.db_pool, message_id).await?;
+
+    if let Err(e) = channels::cleanup_room_channels(ctx, &data.db_pool, message_id).await {
+        error!("error cleaning up channels of room {}: {e}", message_id.0);
+    }
+    roles::refresh_participant_role(ctx, &data.db_pool, message_id).await;
+
+    let room_num = match record {
+        Some(r) => r.num,
+        // finished and cancelled rooms only had to be forgotten
+        None => return Ok(()),
+    };
+
+    info!("cancelled room {}: {reason}", message_id.0);
+
+    let content = format!("Room #{room_num} has been cancelled: {reason}");
+    for user_id in get_roster(&data.db_pool, message_id).await? {
+        // failures are logged by `dm_user`, the remaining players should still be told
+        let _ = dm_user(ctx, UserId(user_id as u64), &content).await;
+    }
+
+    Ok(())
+}
+
+/// Deletes a room from the database, cleaning up its channels and taking back the access
+/// and roles it gave first. If that fails, the room is kept so removing it can be retried.
+pub async fn delete_room(
+    ctx: &serenity::Context,
+    data: &Data,
+    message_id: MessageId,
+) -> Result<(), Error> {
+    channels::cleanup_room_channels(ctx, &data.db_pool, message_id).await?;
+    // deleted channels took their grants along, this covers channels players were added to
+    channels::revoke_room_access(ctx, &data.db_pool, message_id).await?;
+    roles::strip_participant_role(ctx, &data.db_pool, message_id).await?;
+
+    sqlx::query!(
+        "DELETE FROM message WHERE message_id = $1",
+        message_id.0 as i64
+    )
+    .execute(&data.db_pool)
+    .await?;
+
+    // only remove from our local cache if database removal is successful
+    data.messages.remove(&message_id.0);
+
+    info!("removed room {}", message_id.0);
+
+    Ok(())
+}
+
+/// Signups only happen through reactions while a room is open, so only open rooms keep them.
+async fn sync_reactions(
+    ctx: &serenity::Context,
+    channel_id: ChannelId,
+    message_id: MessageId,
+    status: RoomStatus,
+) -> Result<(), Error> {
+    if status == RoomStatus::Open {
+        channel_id.create_reaction(ctx, message_id, '✅').await?;
+        channel_id.create_reaction(ctx, message_id, '❌').await?;
+    } else {
+        channel_id
+            .delete_reaction_emoji(ctx, message_id, '✅')
+            .await?;
+        channel_id
+            .delete_reaction_emoji(ctx, message_id, '❌')
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Returns the IDs of everyone signed up for a room, in signup order.
+pub async fn get_roster(pool: &PgPool, message_id: MessageId) -> Result<Vec<i64>, Error> {
+    let records = sqlx::query!(
+        "SELECT user_id FROM signup WHERE message_id = $1 ORDER BY react_num",
+        message_id.0 as i64
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(records.into_iter().map(|r| r.user_id).collect())
+}
+
+/// Splits a room's roster into its main players and its reserves, in signup order.
+pub async fn get_roster_split(
+    pool: &PgPool,
+    message_id: MessageId,
+) -> Result<(Vec<i64>, Vec<i64>), Error> {
+    let capacity = get_room_capacity(message_id, pool).await?;
+    let mut main = get_roster(pool, message_id).await?;
+    let reserves = main.split_off((capacity.main as usize).min(main.len()));
+
+    Ok((main, reserves))
+}
+
+/// What happened when a player tried to sign up for a room.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Registration {
+    /// The player signed up at `position` in the roster, counting from 0.
+    Registered {
+        position: i64,
+        capacity: Capacity,
+    },
+    /// The room doesn't accept signups.
+    Closed,
+    AlreadyRegistered,
+    /// The player is signed up for as many active rooms as allowed, listed by number.
+    LimitReached(Vec<i32>),
+    Full,
+}
+
+/// Signs a player up for a room unless it is full or they reached the guild's room limit
+/// of `limit` active rooms, `0` meaning unlimited. `actor` is whoever asked for the signup.
+/// Signups `by_host` may also add players to rooms with closed registrations.
+///
+/// All checks happen in the same transaction as the signup itself, while holding locks on
+/// the room and the player, so simultaneous signups can't overfill a room or get a player
+/// into more rooms than allowed.
+pub async fn register_player(
+    pool: &PgPool,
+    guild_id: GuildId,
+    message_id: MessageId,
+    user_id: UserId,
+    limit: i32,
+    by_host: bool,
+    actor: UserId,
+) -> Result<Registration, Error> {
+    let mut tx = pool.begin().await?;
+
+    let registration =
+        register_in_transaction(&mut tx, guild_id, message_id, user_id, limit, by_host).await?;
+
+    if matches!(registration, Registration::Registered { .. }) {
+        // recording the event in the same transaction keeps the history in signup order
+        record_event(
+            &mut tx,
+            message_id,
+            user_id,
+            SignupEventKind::Join,
+            Some(actor),
+            None,
+        )
+        .await?;
+        tx.commit().await?;
+    }
+
+    Ok(registration)
+}
+
+/// Moves a player's signup from one room to the end of another's roster on behalf of a
+/// host. The player keeps their signup in `from` unless they could be registered for `to`.
+pub async fn move_player(
+    pool: &PgPool,
+    guild_id: GuildId,
+    from: MessageId,
+    to: MessageId,
+    user_id: UserId,
+    limit: i32,
+    actor: UserId,
+) -> Result<Registration, Error> {
+    let mut tx = pool.begin().await?;
+
+    // lock the player before touching their signups, as `register_in_transaction` does
+    sqlx::query("SELECT pg_advisory_xact_lock($1)")
+        .bind(user_id.0 as i64)
+        .execute(&mut tx)
+        .await?;
+
+    let removed = sqlx::query!(
+        "DELETE FROM signup WHERE message_id = $1 AND user_id = $2",
+        from.0 as i64,
+        user_id.0 as i64,
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+
+    if removed == 0 {
+        return Err("That player isn't signed up for the room they should be moved from.".into());
+    }
+
+    let registration = register_in_transaction(&mut tx, guild_id, to, user_id, limit, true).await?;
+
+    if matches!(registration, Registration::Registered { .. }) {
+        let nums = sqlx::query!(
+            r#"SELECT (SELECT num FROM message WHERE message_id = $1) as "from!",
+            (SELECT num FROM message WHERE message_id = $2) as "to!""#,
+            from.0 as i64,
+            to.0 as i64,
+        )
+        .fetch_one(&mut tx)
+        .await?;
+
+        let detail = format!("to Room #{}", nums.to);
+        let moved = SignupEventKind::Moved;
+        record_event(&mut tx, from, user_id, moved, Some(actor), Some(&detail)).await?;
+
+        let detail = format!("from Room #{}", nums.from);
+        record_event(&mut tx, to, user_id, moved, Some(actor), Some(&detail)).await?;
+
+        tx.commit().await?;
+    }
+
+    Ok(registration)
+}
+
+/// Takes a player off a room's roster and records why in the room's history, both in one
+/// transaction. Players who `left_server` are also listed as gone from the room. Returns
+/// `false` if they weren't signed up.
+pub async fn withdraw_player(
+    pool: &PgPool,
+    message_id: MessageId,
+    user_id: UserId,
+    kind: SignupEventKind,
+    actor: Option<UserId>,
+    detail: Option<&str>,
+    left_server: bool,
+) -> Result<bool, Error> {
+    let mut tx = pool.begin().await?;
+
+    // same locking order as `register_in_transaction`, the player before the room
+    sqlx::query("SELECT pg_advisory_xact_lock($1)")
+        .bind(user_id.0 as i64)
+        .execute(&mut tx)
+        .await?;
+
+    sqlx::query!(
+        "SELECT 1 as locked FROM message WHERE message_id = $1 FOR UPDATE",
+        message_id.0 as i64
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    let removed = sqlx::query!(
+        "DELETE FROM signup WHERE message_id = $1 AND user_id = $2",
+        message_id.0 as i64,
+        user_id.0 as i64,
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+
+    if removed == 0 {
+        return Ok(false);
+    }
+
+    if left_server {
+        sqlx::query!(
+            "INSERT INTO departure (message_id, user_id) VALUES ($1, $2)
+            ON CONFLICT (message_id, user_id) DO UPDATE SET left_at = now()",
+            message_id.0 as i64,
+            user_id.0 as i64,
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+
+    record_event(&mut tx, message_id, user_id, kind, actor, detail).await?;
+    tx.commit().await?;
+
+    Ok(true)
+}
+
+async fn register_in_transaction(
+    tx: &mut Transaction<'_, Postgres>,
+    guild_id: GuildId,
+    message_id: MessageId,
+    user_id: UserId,
+    limit: i32,
+    by_host: bool,
+) -> Result<Registration, Error> {
+    // the player's signups for other rooms don't lock this room, so they need their own
+    // lock. players are always locked before rooms, so two signups can't deadlock
+    sqlx::query("SELECT pg_advisory_xact_lock($1)")
+        .bind(user_id.0 as i64)
+        .execute(&mut *tx)
+        .await?;
+
+    let room = sqlx::query!(
+        r#"SELECT status as "status: RoomStatus", main_slots, reserve_slots FROM message
+        WHERE message_id = $1 FOR UPDATE"#,
+        message_id.0 as i64
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let accepts_signups = if by_host {
+        room.status.accepts_withdrawals()
+    } else {
+        room.status == RoomStatus::Open
+    };
+
+    if !accepts_signups {
+        return Ok(Registration::Closed);
+    }
+
+    let registered = sqlx::query!(
+        r#"SELECT exists (SELECT 1 FROM signup WHERE message_id = $1 AND user_id = $2) as "exists!""#,
+        message_id.0 as i64,
+        user_id.0 as i64,
+    )
+    .fetch_one(&mut *tx)
+    .await?
+    .exists;
+
+    if registered {
+        return Ok(Registration::AlreadyRegistered);
+    }
+
+    if limit > 0 {
+        let active_rooms = sqlx::query!(
+            "SELECT num FROM signup s JOIN message m ON s.message_id = m.message_id
+            WHERE user_id = $1 AND guild_id = $2 AND status IN ('open', 'closed') ORDER BY num",
+            user_id.0 as i64,
+            guild_id.0 as i64,
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        if active_rooms.len() >= limit as usize {
+            return Ok(Registration::LimitReached(
+                active_rooms.into_iter().map(|r| r.num).collect(),
+            ));
+        }
+    }
+
+    let capacity = Capacity {
+        main: room.main_slots,
+        reserve: room.reserve_slots,
+    };
+
+    let position = sqlx::query!(
+        r#"SELECT COUNT(*) as "count!" FROM signup WHERE message_id = $1"#,
+        message_id.0 as i64
+    )
+    .fetch_one(&mut *tx)
+    .await?
+    .count;
+
+    if position >= capacity.total() as i64 {
+        return Ok(Registration::Full);
+    }
+
+    sqlx::query!(
+        "INSERT INTO signup (message_id, user_id) VALUES ($1, $2)",
+        message_id.0 as i64,
+        user_id.0 as i64,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    // players who left and came back shouldn't be listed as gone anymore
+    sqlx::query!(
+        "DELETE FROM departure WHERE message_id = $1 AND user_id = $2",
+        message_id.0 as i64,
+        user_id.0 as i64,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(Registration::Registered { position, capacity })
+}
+
+/// Swaps the roster positions of two players signed up for the same room, e.g. to move a
+/// reserve into the main roster.
+pub async fn swap_players(
+    pool: &PgPool,
+    message_id: MessageId,
+    first: UserId,
+    second: UserId,
+) -> Result<(), Error> {
+    let mut tx = pool.begin().await?;
+
+    let records = sqlx::query!(
+        "SELECT user_id, react_num FROM signup
+        WHERE message_id = $1 AND user_id IN ($2, $3) FOR UPDATE",
+        message_id.0 as i64,
+        first.0 as i64,
+        second.0 as i64,
+    )
+    .fetch_all(&mut tx)
+    .await?;
+
+    if records.len() != 2 {
+        return Err("Both players have to be signed up for the room.".into());
+    }
+
+    for (record, other) in records.iter().zip(records.iter().rev()) {
+        sqlx::query!(
+            "UPDATE signup SET react_num = $1 WHERE message_id = $2 AND user_id = $3",
+            other.react_num,
+            message_id.0 as i64,
+            record.user_id,
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Builds an embed listing a room's main roster and reserves, linking to the room if
+/// `link` is given. Hosts also see who was dropped for leaving the guild.
+pub async fn roster_embed(
+    pool: &PgPool,
+    message_id: MessageId,
+    link: Option<String>,
+    show_departures: bool,
+) -> Result<serenity::CreateEmbed, Error> {
+    let room_num = sqlx::query!(
+        "SELECT num FROM message WHERE message_id = $1",
+        message_id.0 as i64
+    )
+    .fetch_one(pool)
+    .await?
+    .num;
+
+    let roster = get_roster(pool, message_id).await?;
+    let capacity = get_room_capacity(message_id, pool).await?;
+
+    let mut embed = serenity::CreateEmbed::default();
+    embed.colour(EMBED_COLOUR);
+
+    let desc_start = if let Some(link) = link {
+        format!("**[Signups for Room #{}]({})**", room_num, link)
+    } else {
+        format!("**Signups for Room #{}**", room_num)
+    };
+
+    if roster.is_empty() {
+        embed.description(format!("{desc_start}\n\nNo signups yet."));
+    } else {
+        embed.description(desc_start);
+        let mut registered = String::new();
+        let mut reserves = String::new();
+
+        let main = capacity.main as usize;
+
+        for (i, user_id) in roster.iter().enumerate() {
+            if i < main {
+                registered.push_str(&format_user_str(*user_id));
+            } else {
+                reserves.push_str(&format_user_str(*user_id));
+            }
+        }
+
+        embed.field(
+            format!("Registered ({}/{})", roster.len().min(main), capacity.main),
+            registered,
+            true,
+        );
+
+        if roster.len() > main {
+            embed.field(
+                format!("Reserves ({}/{})", roster.len() - main, capacity.reserve),
+                reserves,
+                true,
+            );
+        }
+    }
+
+    if show_departures {
+        let departures = sqlx::query!(
+            "SELECT user_id, left_at FROM departure WHERE message_id = $1 ORDER BY left_at",
+            message_id.0 as i64
+        )
+        .fetch_all(pool)
+        .await?;
+
+        if !departures.is_empty() {
+            let left = departures
+                .iter()
+                .map(|d| {
+                    format!(
+                        "<@{0}> ({0}), <t:{1}:R>\n",
+                        d.user_id,
+                        d.left_at.timestamp()
+                    )
+                })
+                .collect::<String>();
+
+            embed.field("Left the server", left, false);
+        }
+    }
+
+    Ok(embed)
+}
+
+fn format_user_str(uid: i64) -> String {
+    format!("<@{uid}> ({uid})\n")
+}
+
+/// Compares a room's current roster to `before`, when the room had `main_before` main
+/// slots, and lets every player whose position changed for the better or worse know. Moves
+/// between the main roster and the reserves are recorded as caused by `actor`, `None`
+/// meaning they followed from a player leaving on their own.
+pub async fn notify_roster_changes(
+    ctx: &serenity::Context,
+    data: &Data,
+    message_id: MessageId,
+    before: &[i64],
+    main_before: i32,
+    actor: Option<UserId>,
+) -> Result<(), Error> {
+    let after = get_roster(&data.db_pool, message_id).await?;
+    let capacity = get_room_capacity(message_id, &data.db_pool).await?;
+    let (main, main_before) = (capacity.main as usize, main_before as usize);
+
+    let room = sqlx::query!(
+        "SELECT num, channel_id FROM message WHERE message_id = $1",
+        message_id.0 as i64
+    )
+    .fetch_one(&data.db_pool)
+    .await?;
+    let (room_num, channel_id) = (room.num, ChannelId(room.channel_id as u64));
+
+    for (new_pos, user_id) in after.iter().enumerate() {
+        let old_pos = match before.iter().position(|u| u == user_id) {
+            Some(pos) => pos,
+            None => continue,
+        };
+
+        let user = UserId(*user_id as u64);
+        let (was_main, is_main) = (old_pos < main_before, new_pos < main);
+
+        let content = if !was_main && is_main {
+            info!(
+                "promoted user {} to the main roster of room {}",
+                user, message_id.0
+            );
+            record_event(
+                &data.db_pool,
+                message_id,
+                user,
+                SignupEventKind::Promote,
+                actor,
+                None,
+            )
+            .await?;
+            format!("You moved from reserve to the main roster for Room #{room_num}.")
+        } else if was_main && !is_main {
+            info!(
+                "moved user {} to the reserves of room {}",
+                user, message_id.0
+            );
+            record_event(
+                &data.db_pool,
+                message_id,
+                user,
+                SignupEventKind::Demote,
+                actor,
+                None,
+            )
+            .await?;
+            format!(
+                "You moved from the main roster to the reserve list for Room #{room_num}. \
+                Your position is {}/{}.",
+                new_pos - main + 1,
+                capacity.reserve
+            )
+        } else if !is_main && new_pos - main < old_pos - main_before {
+            format!(
+                "You moved up the reserve list for Room #{room_num}. \
+                Your position is now {}/{}.",
+                new_pos - main + 1,
+                capacity.reserve
+            )
+        } else {
+            continue;
+        };
+
+        // failing to notify shouldn't stop us from notifying the rest
+        if let Err(e) = notify_user(ctx, user, channel_id, content).await {
+            error!("unable to notify user {} of their new position: {e}", user);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::task::JoinHandle;
+
+    use super::*;
+
+    const GUILD_ID: GuildId = GuildId(1);
+
+    async fn create_room(pool: &PgPool, message_id: u64, num: i32, capacity: Capacity) {
+        sqlx::query(
+            "INSERT INTO message
+            (message_id, guild_id, channel_id, num, main_slots, reserve_slots, signup_mode)
+            VALUES ($1, $2, 1, $3, $4, $5, 'buttons')",
+        )
+        .bind(message_id as i64)
+        .bind(GUILD_ID.0 as i64)
+        .bind(num)
+        .bind(capacity.main)
+        .bind(capacity.reserve)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    /// Waits for every signup, which were all spawned before the first one is awaited.
+    async fn join_all<T>(handles: Vec<JoinHandle<T>>) -> Vec<T> {
+        let mut results = Vec::with_capacity(handles.len());
+        for handle in handles {
+            results.push(handle.await.unwrap());
+        }
+        results
+    }
+
+    #[sqlx::test]
+    async fn concurrent_signups_never_overfill_a_room(pool: PgPool) {
+        let capacity = Capacity {
+            main: 3,
+            reserve: 2,
+        };
+        create_room(&pool, 100, 1, capacity).await;
+
+        let signups = (0..30)
+            .map(|i| {
+                let pool = pool.clone();
+                tokio::spawn(async move {
+                    register_player(
+                        &pool,
+                        GUILD_ID,
+                        MessageId(100),
+                        UserId(1000 + i),
+                        0,
+                        false,
+                        UserId(1000 + i),
+                    )
+                    .await
+                })
+            })
+            .collect();
+
+        let results = join_all(signups)
+            .await
+            .into_iter()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+
+        let mut positions = results
+            .iter()
+            .filter_map(|r| match r {
+                Registration::Registered { position, .. } => Some(*position),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        positions.sort_unstable();
+
+        assert_eq!(positions, (0..capacity.total() as i64).collect::<Vec<_>>());
+        assert!(results
+            .iter()
+            .all(|r| matches!(r, Registration::Registered { .. } | Registration::Full)));
+
+        let roster = get_roster(&pool, MessageId(100)).await.unwrap();
+        assert_eq!(roster.len(), capacity.total() as usize);
+    }
+
+    #[sqlx::test]
+    async fn concurrent_signups_respect_the_room_limit(pool: PgPool) {
+        for num in 1..=20 {
+            create_room(&pool, 100 + num as u64, num, Capacity::default()).await;
+        }
+
+        let signups = (1..=20)
+            .map(|num| {
+                let pool = pool.clone();
+                tokio::spawn(async move {
+                    register_player(
+                        &pool,
+                        GUILD_ID,
+                        MessageId(100 + num),
+                        UserId(1000),
+                        2,
+                        false,
+                        UserId(1000),
+                    )
+                    .await
+                })
+            })
+            .collect();
+
+        let registered = join_all(signups)
+            .await
+            .into_iter()
+            .filter(|r| matches!(r, Ok(Registration::Registered { .. })))
+            .count();
+
+        assert_eq!(registered, 2);
+    }
+}
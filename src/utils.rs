@@ -1,16 +1,120 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::future::Future;
+use std::str::FromStr;
 use std::time::Duration;
 
 use poise::serenity_prelude::{
+    self as serenity,
     parse_message_id_pair,
     parse_message_url,
     ChannelId,
     GuildId,
+    Mentionable,
     MessageId,
     MessageParseError,
 };
 use sqlx::PgPool;
+use tracing::{error, info};
 
-use crate::{Context, Data, Error};
+use crate::room::{SignupMode, DEFAULT_SIGNUP_MODE};
+use crate::{
+    Context,
+    Data,
+    Error,
+    GuildConfig,
+    BUTTON_STR,
+    DEFAULT_MAIN_SLOTS,
+    DEFAULT_RESERVE_SLOTS,
+    DEFAULT_ROOM_LIMIT,
+    EMBED_COLOUR,
+    REACT_STR,
+};
+
+/// How long a message mentioning a user whose DMs are closed stays up.
+const FALLBACK_MESSAGE_LIFETIME: Duration = Duration::from_secs(30);
+
+/// Most main or reserve slots a room may have. Rosters are listed one player per line in a
+/// single embed field, which holds up to 1024 characters.
+pub const MAX_SLOTS: i32 = 20;
+
+/// Discord's error codes for channels, members and roles that don't exist anymore.
+pub const UNKNOWN_CHANNEL: isize = 10003;
+pub const UNKNOWN_MEMBER: isize = 10007;
+pub const UNKNOWN_ROLE: isize = 10011;
+
+/// Number of main and reserve slots in a room.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Capacity {
+    pub main: i32,
+    pub reserve: i32,
+}
+
+impl Capacity {
+    pub fn total(&self) -> i32 {
+        self.main + self.reserve
+    }
+}
+
+impl Default for Capacity {
+    fn default() -> Self {
+        Self {
+            main: DEFAULT_MAIN_SLOTS,
+            reserve: DEFAULT_RESERVE_SLOTS,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CapacityParseError(Cow<'static, str>);
+
+impl fmt::Display for CapacityParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for CapacityParseError {}
+
+impl FromStr for Capacity {
+    type Err = CapacityParseError;
+
+    /// Parses capacities written as `main+reserve`, e.g. `9+6` or `6+0`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const MALFORMED: CapacityParseError = CapacityParseError(Cow::Borrowed(
+            "capacity must be written as `main+reserve`, e.g. `9+6`",
+        ));
+
+        let (main, reserve) = s.split_once('+').ok_or(MALFORMED)?;
+        let main = main.trim().parse::<i32>().map_err(|_| MALFORMED)?;
+        let reserve = reserve.trim().parse::<i32>().map_err(|_| MALFORMED)?;
+
+        if main < 1 || reserve < 0 {
+            return Err(CapacityParseError(Cow::Borrowed(
+                "a room needs at least one main slot and cannot have negative reserves",
+            )));
+        }
+
+        if main > MAX_SLOTS || reserve > MAX_SLOTS {
+            return Err(CapacityParseError(Cow::Owned(format!(
+                "a room can have at most {MAX_SLOTS} main slots and {MAX_SLOTS} reserve slots"
+            ))));
+        }
+
+        Ok(Self { main, reserve })
+    }
+}
+
+/// Whether a request to Discord failed with one of the JSON error `codes`.
+pub fn is_discord_error(e: &serenity::Error, codes: &[isize]) -> bool {
+    match e {
+        serenity::Error::Http(e) => matches!(
+            &**e,
+            serenity::HttpError::UnsuccessfulRequest(r) if codes.contains(&r.error.code)
+        ),
+        _ => false,
+    }
+}
 
 pub fn get_message_link(message_id: u64, data: &Data, guild_id: GuildId) -> Option<String> {
     data.guild_configs
@@ -20,11 +124,69 @@ pub fn get_message_link(message_id: u64, data: &Data, guild_id: GuildId) -> Opti
         .map(|i| MessageId(message_id).link(ChannelId(i), Some(guild_id)))
 }
 
-pub async fn get_message_id(input: &str, pool: &PgPool) -> Result<MessageId, Error> {
+pub fn get_default_capacity(data: &Data, guild_id: GuildId) -> Capacity {
+    data.guild_configs
+        .get(&guild_id.0)
+        .and_then(|c| c.capacity)
+        .unwrap_or_default()
+}
+
+/// Returns how players sign up for rooms the guild hosts from now on.
+pub fn get_signup_mode(data: &Data, guild_id: GuildId) -> SignupMode {
+    data.guild_configs
+        .get(&guild_id.0)
+        .and_then(|c| c.signup_mode)
+        .unwrap_or(DEFAULT_SIGNUP_MODE)
+}
+
+/// Returns the number of active rooms a player may sign up for, `0` meaning unlimited.
+pub fn get_room_limit(data: &Data, guild_id: GuildId) -> i32 {
+    data.guild_configs
+        .get(&guild_id.0)
+        .and_then(|c| c.room_limit)
+        .unwrap_or(DEFAULT_ROOM_LIMIT)
+}
+
+pub async fn get_room_capacity(message_id: MessageId, pool: &PgPool) -> Result<Capacity, Error> {
+    let res = sqlx::query!(
+        "SELECT main_slots, reserve_slots FROM message WHERE message_id = $1",
+        message_id.0 as i64
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(Capacity {
+        main: res.main_slots,
+        reserve: res.reserve_slots,
+    })
+}
+
+pub fn spots_footer(capacity: Capacity, signups: i64, mode: SignupMode) -> String {
+    let hint = match mode {
+        SignupMode::Reactions => REACT_STR,
+        SignupMode::Buttons => BUTTON_STR,
+    };
+
+    format!(
+        "{}/{} spots available | {hint}",
+        (capacity.total() as i64 - signups).max(0),
+        capacity.total()
+    )
+}
+
+pub async fn get_message_id(
+    input: &str,
+    guild_id: GuildId,
+    pool: &PgPool,
+) -> Result<MessageId, Error> {
     if let Ok(i) = input.parse::<i32>() {
-        if let Some(res) = sqlx::query!("SELECT message_id FROM message WHERE num = $1", i as i32)
-            .fetch_optional(pool)
-            .await?
+        if let Some(res) = sqlx::query!(
+            "SELECT message_id FROM message WHERE guild_id = $1 AND num = $2",
+            guild_id.0 as i64,
+            i as i32
+        )
+        .fetch_optional(pool)
+        .await?
         {
             return Ok(MessageId(res.message_id as u64));
         }
@@ -33,11 +195,26 @@ pub async fn get_message_id(input: &str, pool: &PgPool) -> Result<MessageId, Err
     let extract_from_message_id = || Some(MessageId(input.parse().ok()?));
     let extract_from_message_url = || Some(parse_message_url(&input.replace("canary.", ""))?.2);
 
-    parse_message_id_pair(input)
+    let message_id = parse_message_id_pair(input)
         .map(|(_, m)| m)
         .or_else(extract_from_message_id)
         .or_else(extract_from_message_url)
-        .ok_or_else(|| MessageParseError::Malformed.into())
+        .ok_or(MessageParseError::Malformed)?;
+
+    // only rooms hosted in the invoking guild may be referenced
+    let record = sqlx::query!(
+        r#"SELECT exists (SELECT 1 FROM message WHERE guild_id = $1 AND message_id = $2) as "exists!""#,
+        guild_id.0 as i64,
+        message_id.0 as i64,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    if record.exists {
+        Ok(message_id)
+    } else {
+        Err("unable to find room with given number or message ID".into())
+    }
 }
 
 pub async fn confirm_prompt(ctx: &Context<'_>, timeout: f32, answer: &str) -> bool {
@@ -50,3 +227,243 @@ pub async fn confirm_prompt(ctx: &Context<'_>, timeout: f32, answer: &str) -> bo
         Some(m) if m.content == answer,
     )
 }
+
+/// Joins `parts` with `separator` into as few messages as possible without going over
+/// Discord's message length limit.
+pub fn split_message(parts: &[String], separator: &str) -> Vec<String> {
+    const LIMIT: usize = 2000;
+
+    let mut messages = Vec::new();
+    let mut current = String::new();
+
+    for part in parts {
+        if !current.is_empty() && current.len() + separator.len() + part.len() > LIMIT {
+            messages.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push_str(separator);
+        }
+        current.push_str(part);
+    }
+
+    if !current.is_empty() {
+        messages.push(current);
+    }
+
+    messages
+}
+
+pub async fn dm_user(
+    ctx: &serenity::Context,
+    user_id: serenity::UserId,
+    content: impl std::fmt::Display,
+) -> Result<serenity::Message, Error> {
+    match user_id.create_dm_channel(&ctx).await {
+        Ok(c) => match c.say(&ctx, content).await {
+            Ok(m) => Ok(m),
+            Err(e) => {
+                error!("unable to dm user {}, error: {}", user_id, e);
+                Err(e.into())
+            },
+        },
+        Err(e) => {
+            error!(
+                "unable to create dm channel with user {}, error: {}",
+                user_id, e
+            );
+            Err(e.into())
+        },
+    }
+}
+
+/// DMs a user, mentioning them in `fallback` instead if their DMs are closed. The fallback
+/// message deletes itself shortly after, so it doesn't clutter the channel.
+pub async fn notify_user(
+    ctx: &serenity::Context,
+    user_id: serenity::UserId,
+    fallback: ChannelId,
+    content: impl std::fmt::Display,
+) -> Result<(), Error> {
+    if dm_user(ctx, user_id, &content).await.is_ok() {
+        return Ok(());
+    }
+
+    let msg = fallback
+        .send_message(ctx, |m| {
+            m.content(format!(
+                "{} {content}\n*I couldn't DM you, so this message will disappear shortly.*",
+                user_id.mention()
+            ))
+            .allowed_mentions(|a| a.users([user_id]))
+        })
+        .await?;
+
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(FALLBACK_MESSAGE_LIFETIME).await;
+        if let Err(e) = msg.delete(&ctx).await {
+            error!("unable to delete fallback message {}, error: {}", msg.id, e);
+        }
+    });
+
+    Ok(())
+}
+
+/// Returns true if the user may manage the guild's rooms, meaning they are a moderator or
+/// have the host role.
+pub async fn can_manage_rooms(
+    ctx: &serenity::Context,
+    data: &Data,
+    guild_id: GuildId,
+    user_id: serenity::UserId,
+) -> Result<bool, Error> {
+    let member = guild_id.member(ctx, user_id).await?;
+    if let Ok(permissions) = member.permissions(ctx) {
+        if permissions.contains(serenity::Permissions::BAN_MEMBERS) {
+            return Ok(true);
+        }
+    }
+
+    let host_role_id = if let Some(GuildConfig {
+        host_id: Some(id), ..
+    }) = data.guild_configs.get(&guild_id.0).map(|c| *c)
+    {
+        id
+    } else {
+        return Err("The host role is not set.".into());
+    };
+
+    // we don't want to show any specific error in this case
+    Ok(member.roles.contains(&host_role_id.into()))
+}
+
+/// How many requests of a bulk operation are in flight at once. serenity already waits out
+/// rate limits, this only keeps a large roster from queueing everything up front.
+pub const BATCH_SIZE: usize = 5;
+
+/// An embed that reports how far a bulk operation got, edited after every batch. Without a
+/// channel to report in, progress is only logged.
+pub struct Progress {
+    ctx: serenity::Context,
+    title: String,
+    message: Option<serenity::Message>,
+}
+
+impl Progress {
+    pub async fn start(
+        ctx: &serenity::Context,
+        channel_id: Option<ChannelId>,
+        title: impl Into<String>,
+        total: usize,
+    ) -> Self {
+        let title = title.into();
+        let message = match channel_id {
+            Some(channel_id) => {
+                match channel_id
+                    .send_message(ctx, |m| {
+                        m.embed(|e| {
+                            e.title(&title)
+                                .description(format!("0/{total} done"))
+                                .colour(EMBED_COLOUR)
+                        })
+                    })
+                    .await
+                {
+                    Ok(message) => Some(message),
+                    Err(e) => {
+                        error!("unable to send progress report to {channel_id}: {e}");
+                        None
+                    },
+                }
+            },
+            None => None,
+        };
+
+        Self {
+            ctx: ctx.clone(),
+            title,
+            message,
+        }
+    }
+
+    pub async fn update(&mut self, done: usize, total: usize) {
+        info!("{}: {done}/{total} done", self.title);
+        self.edit(format!("{done}/{total} done")).await;
+    }
+
+    /// Replaces the progress with the final report, cut short if it doesn't fit in an embed.
+    pub async fn finish(mut self, report: &str) {
+        const LIMIT: usize = 4096;
+        const CUT_OFF_SPACE: usize = 32;
+
+        let mut description = String::new();
+        let mut cut_off = 0;
+        for line in report.lines() {
+            if cut_off > 0 || description.len() + line.len() + 1 > LIMIT - CUT_OFF_SPACE {
+                cut_off += 1;
+                continue;
+            }
+            description.push_str(line);
+            description.push('\n');
+        }
+
+        if cut_off > 0 {
+            description.push_str(&format!("...and {cut_off} more"));
+        }
+
+        self.edit(description).await;
+    }
+
+    async fn edit(&mut self, description: String) {
+        let message = match &mut self.message {
+            Some(m) => m,
+            None => return,
+        };
+
+        let title = &self.title;
+        if let Err(e) = message
+            .edit(&self.ctx, |m| {
+                m.embed(|e| e.title(title).description(description).colour(EMBED_COLOUR))
+            })
+            .await
+        {
+            error!("unable to update progress report: {e}");
+        }
+    }
+}
+
+/// Runs `task` for every item, [`BATCH_SIZE`] at a time, and returns each item with its
+/// result in the original order.
+pub async fn run_batched<T, R, F, Fut>(
+    items: Vec<T>,
+    progress: &mut Progress,
+    task: F,
+) -> Vec<(T, Result<R, Error>)>
+where
+    T: Clone,
+    F: Fn(T) -> Fut,
+    Fut: Future<Output = Result<R, Error>> + Send + 'static,
+    R: Send + 'static,
+{
+    let total = items.len();
+    let mut results = Vec::with_capacity(total);
+
+    for batch in items.chunks(BATCH_SIZE) {
+        let handles = batch
+            .iter()
+            .map(|item| (item.clone(), tokio::spawn(task(item.clone()))))
+            .collect::<Vec<_>>();
+
+        for (item, handle) in handles {
+            let result = match handle.await {
+                Ok(result) => result,
+                Err(e) => Err(e.into()),
+            };
+            results.push((item, result));
+        }
+
+        progress.update(results.len(), total).await;
+    }
+
+    results
+}